@@ -0,0 +1,220 @@
+//! Pipelines described in a TOML file.
+//!
+//! ```toml
+//! [names]
+//! TXD = 0
+//!
+//! [source]
+//! type = "vcd"
+//! origin = "first-edge"
+//!
+//! [[stage]]
+//! type = "serial"
+//! baud = 115200
+//! tx = "TXD"
+//!
+//! [sink]
+//! type = "colored"
+//! ```
+//!
+//! Sources (`[source]` or `[[source]]`) and stages (`[[stage]]`) are tables holding the options of
+//! their command line: `key = value` stands for `--key value`, arrays repeat the option, inline
+//! tables give `--key name=value` pairs and `true` sets a flag. `file` is the input of a source, the
+//! inputs given on the command line going to the sources without one. `[names]` names channels of
+//! the first source, as `--name` does. `[sink]` chooses the sink of the events, as `--sink` does.
+
+use anyhow::{anyhow, bail, Context, Result};
+use toml::Value;
+
+use crate::pipeline::Stage;
+use crate::sink::SINKS;
+use crate::stages::STAGES;
+
+/// Stages of a described pipeline and the sink of its events.
+#[derive(Debug, PartialEq)]
+pub struct Description {
+    pub stages: Vec<(String, Vec<String>)>,
+    pub sink: Option<(String, Vec<String>)>,
+}
+
+/// Turns an option value into command line arguments.
+fn push_option(args: &mut Vec<String>, key: &str, value: &Value) -> Result<()> {
+    let flag = if key.chars().count() == 1 {
+        format!("-{}", key)
+    } else {
+        format!("--{}", key)
+    };
+    match value {
+        Value::Boolean(true) => args.push(flag),
+        Value::Boolean(false) => {}
+        Value::String(s) => args.extend([flag, s.clone()]),
+        Value::Integer(i) => args.extend([flag, i.to_string()]),
+        Value::Float(f) => args.extend([flag, f.to_string()]),
+        Value::Array(values) => {
+            for value in values {
+                push_option(args, key, value)?;
+            }
+        }
+        Value::Table(pairs) => {
+            for (name, value) in pairs {
+                let value = match value {
+                    Value::String(s) => s.clone(),
+                    Value::Integer(i) => i.to_string(),
+                    _ => bail!("Invalid value for {}.{}", key, name),
+                };
+                args.extend([flag.clone(), format!("{}={}", name, value)]);
+            }
+        }
+        Value::Datetime(_) => bail!("Invalid value for {}", key),
+    }
+    Ok(())
+}
+
+/// Turns the table of a stage or sink into its name and arguments, `names` being those known.
+fn stage<'a>(
+    table: &Value,
+    default: Option<&str>,
+    mut names: impl Iterator<Item = &'a str>,
+) -> Result<(String, Vec<String>)> {
+    let table = table
+        .as_table()
+        .ok_or_else(|| anyhow!("Stages must be tables"))?;
+    let name = match (table.get("type"), default) {
+        (Some(Value::String(name)), _) => name.as_str(),
+        (None, Some(name)) => name,
+        _ => bail!("Missing stage type"),
+    };
+    if !names.any(|known| known == name) {
+        bail!("Unknown type {:?}", name);
+    }
+
+    let mut args = Vec::new();
+    let mut file = None;
+    for (key, value) in table {
+        match (key.as_str(), value) {
+            ("type", _) => {}
+            ("file", Value::String(path)) => file = Some(path.clone()),
+            (key, value) => push_option(&mut args, key, value)
+                .with_context(|| format!("In the options of {}", name))?,
+        }
+    }
+    args.extend(file);
+    Ok((name.to_owned(), args))
+}
+
+fn stage_names() -> impl Iterator<Item = &'static str> {
+    STAGES.iter().map(|stage| stage.name)
+}
+
+/// Reads a pipeline description, `inputs` being the capture files given on the command line.
+pub fn parse(content: &str, inputs: &[String]) -> Result<Description> {
+    let description = content.parse::<Value>()?;
+    let sources = match description.get("source") {
+        None => Vec::new(),
+        Some(Value::Array(sources)) => sources.iter().collect(),
+        Some(source) => vec![source],
+    };
+    let mut inputs = inputs.iter();
+    let mut stages = Vec::new();
+    for source in sources {
+        let (name, mut args) =
+            stage(source, Some("auto"), stage_names()).context("Invalid source")?;
+        if !Stage::get(&name).is_source() {
+            bail!("{} is not a source", name);
+        }
+        // the inputs of the command line feed the sources without a file
+        if source.get("file").is_none() {
+            args.extend(inputs.next().cloned());
+        }
+        stages.push((name, args));
+    }
+    // the remaining ones are detected
+    stages.extend(inputs.map(|input| ("auto".to_owned(), vec![input.clone()])));
+    if stages.is_empty() {
+        stages.push(("auto".to_owned(), Vec::new()));
+    }
+
+    if let Some(names) = description.get("names") {
+        push_option(&mut stages[0].1, "name", names).context("Invalid names")?;
+    }
+
+    if let Some(list) = description.get("stage") {
+        let list = list
+            .as_array()
+            .ok_or_else(|| anyhow!("Stages must be given as [[stage]]"))?;
+        for (index, table) in list.iter().enumerate() {
+            stages.push(
+                stage(table, None, stage_names())
+                    .with_context(|| format!("Invalid stage {}", index + 1))?,
+            );
+        }
+    }
+
+    let sink = description
+        .get("sink")
+        .map(|table| stage(table, None, SINKS.iter().map(|sink| sink.name)))
+        .transpose()
+        .context("Invalid sink")?;
+    Ok(Description { stages, sink })
+}
+
+/// Reads a pipeline description file.
+pub fn load(path: &str, inputs: &[String]) -> Result<Description> {
+    let content = std::fs::read_to_string(path).with_context(|| format!("Reading {:?}", path))?;
+    parse(&content, inputs).with_context(|| format!("Parsing {:?}", path))
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn turns_tables_into_arguments() {
+        let description = r#"
+            names = { TXD = 0, EN = "board.en" }
+
+            [source]
+            type = "vcd"
+            map = ["top.tx=0", "top.en=1"]
+
+            [[stage]]
+            type = "glitch"
+            width = "20ns"
+
+            [[stage]]
+            type = "serial"
+            b = 115200
+            tx = "TXD"
+            verbose = true
+
+            [sink]
+            type = "quiet"
+        "#;
+        let args = |args: &[&str]| args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let description = super::parse(description, &["capture.vcd".to_string()]).unwrap();
+        assert_eq!(
+            vec![
+                (
+                    "vcd".to_string(),
+                    args(&[
+                        "--map",
+                        "top.tx=0",
+                        "--map",
+                        "top.en=1",
+                        "capture.vcd",
+                        "--name",
+                        "EN=board.en",
+                        "--name",
+                        "TXD=0"
+                    ])
+                ),
+                ("glitch".to_string(), args(&["--width", "20ns"])),
+                (
+                    "serial".to_string(),
+                    args(&["-b", "115200", "--tx", "TXD", "--verbose"])
+                ),
+            ],
+            description.stages
+        );
+        assert_eq!(Some(("quiet".to_string(), Vec::new())), description.sink);
+        assert!(super::parse("[[stage]]\ntype = \"nope\"", &[]).is_err());
+    }
+}
@@ -0,0 +1,255 @@
+//! Suppresses the pulses shorter than a given width on selected channels.
+//!
+//! A change is only kept if the channel then stays stable for at least the width, the kept changes
+//! retain their original timestamp. Samples are hence delayed by up to the width.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use anyhow::Result;
+use clap::Arg;
+
+use crate::pipeline::{self, Event, EventIterator, Timestamp};
+use crate::source::{channels, Bits, Sample};
+
+/// Channels whose state differs between two samples.
+fn changes(a: &Sample, b: &Sample) -> Bits {
+    &(&(&a.levels ^ &b.levels) | &(&a.unknown ^ &b.unknown)) | &(&a.high_z ^ &b.high_z)
+}
+
+/// Takes the state of the channels in `mask` from `from`.
+fn update(to: &mut Sample, from: &Sample, mask: &Bits) {
+    for (to, from) in [
+        (&mut to.levels, &from.levels),
+        (&mut to.unknown, &from.unknown),
+        (&mut to.high_z, &from.high_z),
+    ] {
+        to.remove(mask);
+        *to |= &(from & mask);
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Stats {
+    count: u64,
+    shortest: Timestamp,
+}
+
+pub struct GlitchFilter<T> {
+    it: T,
+    width: Timestamp,
+    /// Channels to filter, all of them if `None`.
+    mask: Option<Bits>,
+
+    /// Input events not decided yet.
+    queue: VecDeque<(Timestamp, Result<Sample>)>,
+    /// Last raw sample that left the queue.
+    raw: Option<Sample>,
+    output: Option<Sample>,
+    ended: bool,
+
+    stats: BTreeMap<usize, Stats>,
+}
+
+impl<T> GlitchFilter<T> {
+    pub fn new(input: T, width: Timestamp, mask: Option<Bits>) -> Self {
+        Self {
+            it: input,
+            width,
+            mask,
+            queue: VecDeque::new(),
+            raw: None,
+            output: None,
+            ended: false,
+            stats: BTreeMap::new(),
+        }
+    }
+
+    /// Decides the oldest queued sample, returning the sample to emit if any.
+    fn decide(&mut self, ts: Timestamp, sample: Sample) -> Option<Sample> {
+        let (raw, output) = match (self.raw.replace(sample.clone()), self.output.take()) {
+            (Some(raw), Some(output)) => (raw, output),
+            // the first sample is always kept
+            _ => {
+                self.output = Some(sample.clone());
+                return Some(sample);
+            }
+        };
+
+        let mut filtered = changes(&raw, &sample);
+        if let Some(mask) = &self.mask {
+            filtered = &filtered & mask;
+        }
+        let mut kept = Bits::default();
+        for bit in filtered.iter() {
+            // the next change of the channel within the width, if any
+            let glitch = self
+                .queue
+                .iter()
+                .take_while(|(next_ts, _)| *next_ts < ts + self.width)
+                .find_map(|(next_ts, next)| match next {
+                    Ok(next) if changes(&sample, next).get(bit) => Some(*next_ts - ts),
+                    _ => None,
+                });
+
+            match glitch {
+                None => kept.set(bit, true),
+                // pulses only count when they would have changed the output
+                Some(duration) if changes(&output, &sample).get(bit) => {
+                    let stats = self.stats.entry(bit).or_insert(Stats {
+                        count: 0,
+                        shortest: duration,
+                    });
+                    stats.count += 1;
+                    stats.shortest = stats.shortest.min(duration);
+                }
+                Some(_) => {}
+            }
+        }
+
+        // the filtered channels hold their output level unless their change is kept
+        let mut held = changes(&output, &sample);
+        if let Some(mask) = &self.mask {
+            held = &held & mask;
+        }
+        held.remove(&kept);
+
+        let mut new_output = sample;
+        update(&mut new_output, &output, &held);
+        let changed = new_output != output;
+        self.output = Some(new_output);
+        if changed {
+            self.output.clone()
+        } else {
+            None
+        }
+    }
+
+    fn report(&self) {
+        if self.stats.is_empty() {
+            eprintln!("glitch: no pulse shorter than {:.9}s", self.width);
+        }
+        for (bit, stats) in &self.stats {
+            eprintln!(
+                "glitch: channel {}: {} pulse(s) suppressed, shortest {:.9}s",
+                bit, stats.count, stats.shortest
+            );
+        }
+    }
+}
+
+impl<T> Iterator for GlitchFilter<T>
+where
+    T: Iterator<Item = Event>,
+{
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            // the oldest sample can be decided once the input covers its width
+            let ready = match (self.queue.front(), self.queue.back()) {
+                (Some((first, _)), Some((last, _))) => self.ended || *last >= *first + self.width,
+                _ => false,
+            };
+
+            if ready {
+                let (ts, sample) = self.queue.pop_front().unwrap();
+                let event = match sample {
+                    Ok(sample) => match self.decide(ts, sample) {
+                        Some(sample) => (ts, Ok(Box::new(sample) as _)),
+                        None => continue,
+                    },
+                    Err(e) => (ts, Err(e)),
+                };
+                return Some(event);
+            }
+
+            if self.ended {
+                return None;
+            }
+            match self.it.next() {
+                Some((ts, ev)) => {
+                    let sample = ev.map(|ev| *pipeline::downcast::<Sample>(ev));
+                    self.queue.push_back((ts, sample));
+                }
+                None => {
+                    self.ended = true;
+                    self.report();
+                }
+            }
+        }
+    }
+}
+
+impl<T: 'static + Iterator<Item = Event>> EventIterator for GlitchFilter<T> {
+    fn into_iterator(self: Box<Self>) -> Box<dyn Iterator<Item = Event>> {
+        self
+    }
+    fn event_type(&self) -> std::any::TypeId {
+        std::any::TypeId::of::<Sample>()
+    }
+    fn event_type_name(&self) -> &'static str {
+        std::any::type_name::<Sample>()
+    }
+}
+
+pub fn build(pipeline: &mut Vec<Box<dyn EventIterator>>, args: &[String]) {
+    let arg_matches = clap::SubCommand::with_name("glitch")
+        .setting(clap::AppSettings::NoBinaryName)
+        .args(&[
+            Arg::from_usage("-v, --verbose verbose 'set to print events to stdout.'"),
+            Arg::from_usage(
+                "-w, --width <time> 'Shortest valid pulse, in seconds unless suffixed by ms, us or ns.'",
+            ),
+            Arg::from_usage("-c, --channel [channel]... 'Channels to filter, all by default.'")
+                .number_of_values(1),
+        ])
+        .get_matches_from(args);
+
+    let width = pipeline::parse_time(arg_matches.value_of("width").unwrap())
+        .unwrap_or_else(|e| clap::Error::value_validation_auto(e.to_string()).exit());
+    let mask = channels::values_of(&arg_matches, "channel")
+        .map(|channels| channels.into_iter().collect::<Bits>());
+
+    let it = pipeline::pop_input(pipeline, "glitch", &[]).into_iterator();
+    let node = Box::new(GlitchFilter::new(it, width, mask));
+    pipeline.push(node);
+}
+
+#[cfg(test)]
+mod test {
+    use crate::pipeline::Timestamp;
+    use crate::source::Bits;
+    use crate::test_util::{levels, samples};
+
+    #[test]
+    fn suppresses_short_pulses() {
+        // channel 0 bounces, channel 1 has a short pulse that is not filtered
+        let input = samples(&[
+            (0., 0b00),
+            (10., 0b01),
+            (10.5, 0b00),
+            (11., 0b01),
+            (20., 0b11),
+            (20.2, 0b01),
+            (30., 0b00),
+            (30.1, 0b01),
+            (40., 0b00),
+        ]);
+
+        let filter = super::GlitchFilter::new(
+            input,
+            Timestamp::from_ps(1_000_000_000_000),
+            Some(Bits::from(0b01)),
+        );
+        assert_eq!(
+            vec![
+                (0., 0b00),
+                (11., 0b01),
+                (20., 0b11),
+                (20.2, 0b01),
+                (40., 0b00)
+            ],
+            levels(filter)
+        );
+    }
+}
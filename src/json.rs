@@ -0,0 +1,165 @@
+//! Structured form of the events, written by the machine readable sinks.
+//!
+//! Events are serialized with serde: structs are objects of their fields, enums carrying data are
+//! objects naming their variant in `kind` and holding its data in `value`, while field-less enums
+//! are just the name of their variant. Byte buffers are hex strings.
+
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, Schema, SchemaObject, SingleOrVec};
+use schemars::{JsonSchema, Map as Definitions};
+use serde::Serializer;
+use serde_json::{json, Map, Value};
+
+/// Serializes bytes as a hex string, e.g. `"0a1b"`, for `#[serde(serialize_with = "json::hex")]`.
+pub fn hex<T, S>(bytes: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: AsRef<[u8]> + ?Sized,
+    S: Serializer,
+{
+    let hex: String = bytes
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    serializer.serialize_str(&hex)
+}
+
+/// Fields of an event, its kind for field-less variants and its value for others than objects.
+pub fn object(value: Value) -> Map<String, Value> {
+    match value {
+        Value::Object(object) => object,
+        Value::String(kind) => std::iter::once(("kind".to_owned(), json!(kind))).collect(),
+        value => std::iter::once(("value".to_owned(), value)).collect(),
+    }
+}
+
+/// Dotted paths of the fields `T` can be serialized with, as listed by `object` and the nested
+/// objects, e.g. `value.token.address`.
+pub fn paths<T: JsonSchema>() -> Vec<String> {
+    let root = SchemaGenerator::default().into_root_schema_for::<T>();
+    let mut paths = Vec::new();
+    list_paths(&root.definitions, &root.schema, "", &mut paths);
+    paths
+}
+
+fn list_paths(
+    definitions: &Definitions<String, Schema>,
+    schema: &SchemaObject,
+    path: &str,
+    paths: &mut Vec<String>,
+) {
+    if let Some(reference) = &schema.reference {
+        let name = reference.trim_start_matches("#/definitions/");
+        if let Some(Schema::Object(schema)) = definitions.get(name) {
+            list_paths(definitions, schema, path, paths);
+        }
+        return;
+    }
+    if schema.instance_type == Some(SingleOrVec::Single(Box::new(InstanceType::Null))) {
+        return;
+    }
+
+    // fields of objects, and alternatives of enums and options
+    let mut nested = Vec::new();
+    if let Some(object) = &schema.object {
+        for (key, field) in &object.properties {
+            let field_path = match path {
+                "" => key.clone(),
+                path => format!("{}.{}", path, key),
+            };
+            nested.extend(
+                objects(std::slice::from_ref(field)).map(|field| (field_path.clone(), field)),
+            );
+        }
+    }
+    if let Some(subschemas) = &schema.subschemas {
+        let alternatives = [&subschemas.one_of, &subschemas.any_of, &subschemas.all_of];
+        for alternative in alternatives.iter().copied().flatten() {
+            nested.extend(objects(alternative).map(|alternative| (path.to_owned(), alternative)));
+        }
+    }
+
+    if nested.is_empty() {
+        // values of field-less enums are held by `kind`, see `object`
+        let path = if path.is_empty() { "kind" } else { path };
+        if !paths.iter().any(|known| known == path) {
+            paths.push(path.to_owned());
+        }
+    }
+    for (path, schema) in nested {
+        list_paths(definitions, schema, &path, paths);
+    }
+}
+
+fn objects(schemas: &[Schema]) -> impl Iterator<Item = &SchemaObject> {
+    schemas.iter().filter_map(|schema| match schema {
+        Schema::Object(schema) => Some(schema),
+        Schema::Bool(_) => None,
+    })
+}
+
+/// Name of an event type within the crate, e.g. `serial::SerialEvent`.
+pub fn type_name(name: &str) -> &str {
+    name.trim_start_matches(concat!(env!("CARGO_CRATE_NAME"), "::"))
+}
+
+#[cfg(test)]
+mod test {
+    use schemars::JsonSchema;
+    use serde::Serialize;
+    use serde_json::json;
+
+    use super::{object, paths};
+
+    #[derive(Serialize, JsonSchema)]
+    #[serde(tag = "kind", content = "value")]
+    enum Event {
+        Idle,
+        Data(
+            #[serde(serialize_with = "super::hex")]
+            #[schemars(with = "String")]
+            Vec<u8>,
+        ),
+        Transfer {
+            mosi: u8,
+            miso: u8,
+        },
+    }
+
+    #[test]
+    fn names_the_variants() {
+        let fields = |event| object(serde_json::to_value(event).unwrap());
+        assert_eq!(json!({"kind": "Idle"}), json!(fields(Event::Idle)));
+        assert_eq!(
+            json!({"kind": "Data", "value": "0a1bff"}),
+            json!(fields(Event::Data(vec![0x0a, 0x1b, 0xff])))
+        );
+        assert_eq!(
+            json!({"kind": "Transfer", "value": {"mosi": 1, "miso": 2}}),
+            json!(fields(Event::Transfer { mosi: 1, miso: 2 }))
+        );
+        assert_eq!(json!({"value": 3}), json!(object(json!(3))));
+    }
+
+    #[test]
+    fn lists_the_paths_of_the_fields() {
+        assert_eq!(
+            vec!["kind", "value", "value.miso", "value.mosi"],
+            paths::<Event>()
+        );
+        assert_eq!(vec!["kind"], paths::<crate::usb::signal::Signal>());
+        assert_eq!(
+            vec![
+                "kind",
+                "value",
+                "value.data.payload",
+                "value.data.pid",
+                "value.handshake",
+                "value.token.address",
+                "value.token.endpoint",
+                "value.token.token_type"
+            ],
+            paths::<crate::usb::protocol::Event>()
+        );
+    }
+}
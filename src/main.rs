@@ -1,66 +1,88 @@
-use itertools::Itertools;
-
-//mod serial;
-//mod spi;
-//mod spif;
+mod config;
+mod glitch;
+mod json;
+mod merge;
+mod remap;
+mod serial;
+mod spi;
+mod spif;
+mod tee;
 mod usb;
-//mod wizfi310;
+mod window;
+mod wizfi310;
 
 mod pipeline;
 mod sink;
 mod source;
+mod stages;
+#[cfg(test)]
+mod test_util;
 
-const TOP_LEVEL_SUBCOMMANDS: [&'static str; 12] = [
-    "vcd",
-    "logic",
-    "logic2",
-    "spi",
-    "spif",
-    "serial",
-    "wizfi310",
-    "usb::signal",
-    "usb::byte",
-    "usb::packet",
-    "usb::protocol",
-    "usb::device",
-];
+use pipeline::Stage;
+use stages::STAGES;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut pipeline = Vec::new();
 
-    for (sub_command, args) in std::env::args().skip(1).peekable().batching(|it| {
-        it.next().map(|subcmd| {
-            let mut args = it
-                .peeking_take_while(|s| !TOP_LEVEL_SUBCOMMANDS.contains(&s.as_str()))
-                .collect::<Vec<_>>();
+    let mut argv = std::env::args().skip(1).collect::<Vec<_>>();
+    // the options of the whole pipeline come before its stages
+    let mut description = None;
+    let mut sink = None;
+    while let Some(option) = argv
+        .first()
+        .filter(|option| *option == "--pipeline" || *option == "--sink")
+        .cloned()
+    {
+        let value = argv
+            .get(1)
+            .cloned()
+            .unwrap_or_else(|| pipeline::fail(&format!("{} expects a value", option)));
+        argv.drain(..2);
+        if option == "--pipeline" {
+            description = Some(value);
+        } else {
+            // a sink and its options, e.g. "colored"
+            let mut words = value.split_whitespace().map(String::from);
+            let name = words
+                .next()
+                .unwrap_or_else(|| pipeline::fail("--sink expects a sink name"));
+            sink = Some((name, words.collect::<Vec<_>>()));
+        }
+    }
 
-            if it.len() == 0 {
-                args.push("-v".into());
-            }
-            (subcmd, args)
-        })
-    }) {
-        match sub_command.as_str() {
-            "vcd" => source::vcd::build(&mut pipeline, &args),
-            "logic" => source::logic::build(&mut pipeline, &args),
-            "logic2" => source::logic2::build(&mut pipeline, &args),
-            "usb::signal" => usb::signal::build(&mut pipeline, &args),
-            "usb::byte" => usb::byte::build(&mut pipeline, &args),
-            "usb::packet" => usb::packet::build(&mut pipeline, &args),
-            "usb::protocol" => usb::protocol::build(&mut pipeline, &args),
-            "usb::device" => usb::device::build(&mut pipeline, &args),
-            _ => unimplemented!(),
+    let starts_with_source = argv
+        .first()
+        .is_some_and(|first| STAGES.iter().any(|s| s.name == first && s.is_source()));
+    let mut stages = match description {
+        // the remaining arguments are the inputs of the described pipeline
+        Some(path) => {
+            let description =
+                config::load(&path, &argv).unwrap_or_else(|e| pipeline::fail(&format!("{:#}", e)));
+            sink = sink.or(description.sink);
+            description.stages
         }
+        None if starts_with_source => stages::split(argv),
+        // without an explicit source, the format of the input (a file or stdin) is detected
+        None => stages::split(std::iter::once("auto".into()).chain(argv).collect()),
+    };
+    if let Some((name, args)) = sink {
+        sink::install(sink::build(&name, &args));
     }
 
-    assert_eq!(
-        pipeline.len(),
-        1,
-        "The pipeline should resolve to a single iterator"
-    );
-    colored::control::set_override(true);
+    // the sink writes the events of the last stage anyway
+    let (layer, args) = stages.last_mut().unwrap();
+    args.retain(|arg| arg != "-v" && arg != "--verbose");
+    let layer = Stage::get(layer).name;
+    stages::build(&mut pipeline, stages);
+
+    if pipeline.len() > 1 {
+        pipeline::fail(&format!(
+            "The pipeline should resolve to a single iterator but {} are left, use `merge` to combine them",
+            pipeline.len()
+        ));
+    }
     if let Some(event_iterator) = pipeline.pop() {
-        event_iterator.for_each(|_| {});
+        sink::drain(layer, event_iterator);
     }
 
     Ok(())
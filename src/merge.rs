@@ -0,0 +1,292 @@
+//! Merges the events of several captures in a single stream, ordered by timestamp.
+
+use std::any::TypeId;
+
+use anyhow::anyhow;
+use clap::{value_t, Arg};
+use itertools::Itertools;
+
+use crate::pipeline::{self, Event, EventData, EventIterator, Timestamp};
+use crate::source::{channels, Sample};
+
+/// A merged stream and how to align it with the others.
+pub struct Input {
+    it: Box<dyn Iterator<Item = Event>>,
+    offset: Timestamp,
+    /// Channel whose first edge is aligned with the one of the first stream.
+    sync: Option<usize>,
+}
+
+impl Input {
+    pub fn new(
+        it: Box<dyn Iterator<Item = Event>>,
+        offset: Timestamp,
+        sync: Option<usize>,
+    ) -> Self {
+        Self { it, offset, sync }
+    }
+
+    /// Reads the stream up to the first edge of the sync channel, returning the events read and
+    /// the time of the edge.
+    fn find_edge(&mut self, channel: usize) -> (Vec<Event>, Option<Timestamp>) {
+        let mut events = Vec::new();
+        let mut level = None;
+        for (ts, ev) in self.it.by_ref() {
+            let current = ev
+                .as_ref()
+                .ok()
+                .and_then(|ev| pipeline::downcast_ref::<Sample>(ev).level(channel));
+            events.push((ts, ev));
+            if let Some(current) = current {
+                if level.is_some_and(|level| level != current) {
+                    return (events, Some(ts));
+                }
+                level = Some(current);
+            }
+        }
+        (events, None)
+    }
+}
+
+/// K-merges streams once they are moved by their offset.
+///
+/// The streams are only aligned on their sync channel when the first event is requested, as it
+/// requires reading ahead up to the edge.
+pub struct Merge {
+    inputs: Vec<Input>,
+    merged: Option<Box<dyn Iterator<Item = Event>>>,
+    event_type: (TypeId, &'static str),
+}
+
+impl Merge {
+    pub fn new(inputs: Vec<Input>, event_type: (TypeId, &'static str)) -> Self {
+        Self {
+            inputs,
+            merged: None,
+            event_type,
+        }
+    }
+
+    fn align(&mut self) -> Box<dyn Iterator<Item = Event>> {
+        let mut reference = None;
+        let streams = std::mem::take(&mut self.inputs)
+            .into_iter()
+            .enumerate()
+            .map(|(index, mut input)| {
+                let (events, offset) = match input.sync {
+                    None => (Vec::new(), input.offset),
+                    Some(channel) => match input.find_edge(channel) {
+                        (events, Some(edge)) => (
+                            events,
+                            input.offset + (*reference.get_or_insert(edge) - edge),
+                        ),
+                        // the capture is still merged, only moved by its offset
+                        (mut events, None) => {
+                            let ts = events.first().map_or(Timestamp::ZERO, |(ts, _)| *ts);
+                            let error = anyhow!(
+                                "No edge on the sync channel {} of capture {}, it is not aligned",
+                                channel,
+                                index + 1
+                            );
+                            events.insert(0, (ts, Err(error)));
+                            (events, input.offset)
+                        }
+                    },
+                };
+                events
+                    .into_iter()
+                    .chain(input.it)
+                    .map(move |(ts, ev)| (index, (ts + offset, ev)))
+            })
+            .collect::<Vec<_>>();
+        Box::new(
+            streams
+                .into_iter()
+                .kmerge_by(|(a, (ta, _)), (b, (tb, _))| (ta, a) < (tb, b))
+                .map(|(_, event)| event),
+        )
+    }
+}
+
+impl Iterator for Merge {
+    type Item = Event;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.merged.is_none() {
+            self.merged = Some(self.align());
+        }
+        self.merged.as_mut()?.next()
+    }
+}
+
+impl EventIterator for Merge {
+    fn into_iterator(self: Box<Self>) -> Box<dyn Iterator<Item = Event>> {
+        self
+    }
+    fn event_type(&self) -> TypeId {
+        self.event_type.0
+    }
+    fn event_type_name(&self) -> &'static str {
+        self.event_type.1
+    }
+}
+
+pub fn build(pipeline: &mut Vec<Box<dyn EventIterator>>, args: &[String]) {
+    let arg_matches = clap::SubCommand::with_name("merge")
+        .setting(clap::AppSettings::NoBinaryName)
+        .args(&[
+            Arg::from_usage("-v, --verbose verbose 'set to print events to stdout.'"),
+            Arg::from_usage(
+                "-n, --captures [count] 'Number of captures to merge, all those of the pipeline by default.'",
+            ),
+            Arg::from_usage(
+                "--offset [time]... 'Time added to the events of a capture, given for each capture in order.'",
+            )
+            .number_of_values(1)
+            .allow_hyphen_values(true),
+            Arg::from_usage(
+                "--sync [channel]... 'Channel shared by the captures, given for each capture in order: their first edges are aligned.'",
+            )
+            .number_of_values(1),
+        ])
+        .get_matches_from(args);
+
+    let count = if arg_matches.is_present("captures") {
+        value_t!(arg_matches, "captures", usize).unwrap_or_else(|e| e.exit())
+    } else {
+        pipeline.len()
+    };
+    if count < 2 || count > pipeline.len() {
+        pipeline::fail(&format!(
+            "merge expects between 2 and {} captures but got {}",
+            pipeline.len(),
+            count
+        ));
+    }
+
+    let offsets = arg_matches
+        .values_of("offset")
+        .into_iter()
+        .flatten()
+        .map(|v| {
+            pipeline::parse_time(v).unwrap_or_else(|e| {
+                clap::Error::value_validation_auto(format!("offset: {}", e)).exit()
+            })
+        })
+        .collect::<Vec<_>>();
+    let syncs = channels::values_of(&arg_matches, "sync").unwrap_or_default();
+    if offsets.len() > count {
+        clap::Error::value_validation_auto(format!(
+            "{} offsets given for {} captures",
+            offsets.len(),
+            count
+        ))
+        .exit();
+    }
+    if !syncs.is_empty() && syncs.len() != count {
+        clap::Error::value_validation_auto(format!(
+            "{} sync channels given for {} captures",
+            syncs.len(),
+            count
+        ))
+        .exit();
+    }
+
+    let nodes = pipeline.split_off(pipeline.len() - count);
+    let event_type = if nodes.iter().map(|node| node.event_type()).all_equal() {
+        (nodes[0].event_type(), nodes[0].event_type_name())
+    } else {
+        (TypeId::of::<dyn EventData>(), "any event")
+    };
+    let inputs = nodes
+        .into_iter()
+        .enumerate()
+        .map(|(index, node)| {
+            let sync = syncs.get(index).copied();
+            if sync.is_some() && node.event_type() != TypeId::of::<Sample>() {
+                pipeline::fail(&format!(
+                    "merge's sync expects {} but got {}",
+                    std::any::type_name::<Sample>(),
+                    node.event_type_name()
+                ));
+            }
+            let offset = offsets.get(index).copied().unwrap_or(Timestamp::ZERO);
+            Input::new(node.into_iterator(), offset, sync)
+        })
+        .collect();
+    pipeline.push(Box::new(Merge::new(inputs, event_type)));
+}
+
+#[cfg(test)]
+mod test {
+    use std::any::TypeId;
+
+    use super::{Input, Merge};
+    use crate::pipeline::Timestamp;
+    use crate::source::Sample;
+    use crate::test_util::{levels, samples};
+
+    fn merge(inputs: Vec<Input>) -> Vec<(f64, u64)> {
+        let event_type = (TypeId::of::<Sample>(), "Sample");
+        levels(Merge::new(inputs, event_type))
+    }
+
+    #[test]
+    fn merges_captures_moved_by_their_offset() {
+        let first = samples(&[(0., 1), (2., 2)]);
+        let second = samples(&[(0., 10), (1., 20)]);
+        assert_eq!(
+            vec![(0., 1), (0.5, 10), (1.5, 20), (2., 2)],
+            merge(vec![
+                Input::new(first, Timestamp::ZERO, None),
+                Input::new(second, Timestamp::from_secs_f64(0.5), None)
+            ])
+        );
+    }
+
+    #[test]
+    fn aligns_captures_on_the_sync_edge() {
+        let first = samples(&[(0., 0b00), (3., 0b10), (4., 0b11)]);
+        let second = samples(&[(0., 0b100), (1., 0b101), (2., 0b000)]);
+        assert_eq!(
+            vec![
+                (0., 0b00),
+                (2., 0b100),
+                (3., 0b10),
+                (3., 0b101),
+                (4., 0b11),
+                (4., 0b000)
+            ],
+            merge(vec![
+                Input::new(first, Timestamp::ZERO, Some(1)),
+                Input::new(second, Timestamp::ZERO, Some(0)),
+            ])
+        );
+    }
+
+    #[test]
+    fn reports_captures_without_sync_edge() {
+        let first = samples(&[(0., 0b0), (3., 0b1)]);
+        let second = samples(&[(1., 0b10), (2., 0b11)]);
+        let event_type = (TypeId::of::<Sample>(), "Sample");
+        let events = Merge::new(
+            vec![
+                Input::new(first, Timestamp::ZERO, Some(0)),
+                Input::new(second, Timestamp::ZERO, Some(1)),
+            ],
+            event_type,
+        )
+        .map(|(ts, ev)| (ts.as_secs_f64(), ev.err().map(|e| e.to_string())))
+        .collect::<Vec<_>>();
+        let error = "No edge on the sync channel 1 of capture 2, it is not aligned";
+        assert_eq!(
+            vec![
+                (0., None),
+                (1., Some(error.to_owned())),
+                (1., None),
+                (2., None),
+                (3., None)
+            ],
+            events
+        );
+    }
+}
@@ -1,8 +1,13 @@
-use std::fmt::Debug;
-use std::{any::Any, borrow::Borrow};
+use std::any::{Any, TypeId};
+use std::borrow::Borrow;
+use std::fmt::{self, Debug};
+use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
+use std::str::FromStr;
 
 use anyhow::Result;
 use colored::*;
+use schemars::JsonSchema;
+use serde::Serialize;
 
 pub trait EventData: Debug + Any {
     fn as_debug(&self) -> &dyn Debug;
@@ -12,8 +17,10 @@ pub trait EventData: Debug + Any {
     fn type_name(&self) -> &'static str {
         std::any::type_name::<Self>()
     }
+    /// Structured form of the event, see `json`.
+    fn to_json(&self) -> serde_json::Value;
 }
-impl<T: Debug + Any> EventData for T {
+impl<T: Debug + Any + Serialize> EventData for T {
     fn as_debug(&self) -> &dyn Debug {
         self
     }
@@ -26,6 +33,9 @@ impl<T: Debug + Any> EventData for T {
     fn into_any(self: Box<Self>) -> Box<dyn Any> {
         self
     }
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("events are serialized to JSON values")
+    }
 }
 
 pub fn downcast<T: 'static>(event: Box<dyn EventData>) -> Box<T> {
@@ -55,10 +65,397 @@ pub fn downcast_ref<T: 'static>(event: &Box<dyn EventData>) -> &T {
     })
 }
 
-pub type Event = (f64, Result<Box<dyn EventData>>);
+/// Time of an event, or a duration, in picoseconds.
+///
+/// Long captures at high sample rates lose the sub-sample resolution in `f64` seconds, the
+/// conversions to seconds are only meant for display. Arithmetic saturates so that `MIN` and `MAX`
+/// can stand for unbounded times.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Timestamp(i64);
+
+impl Timestamp {
+    pub const ZERO: Timestamp = Timestamp(0);
+    pub const MIN: Timestamp = Timestamp(i64::MIN);
+    pub const MAX: Timestamp = Timestamp(i64::MAX);
+    const PER_SECOND: i64 = 1_000_000_000_000;
+
+    pub const fn from_ps(ps: i64) -> Self {
+        Self(ps)
+    }
+
+    pub const fn as_ps(self) -> i64 {
+        self.0
+    }
+
+    /// Rounds a time in seconds to the nearest picosecond.
+    pub fn from_secs_f64(secs: f64) -> Self {
+        Self((secs * Self::PER_SECOND as f64).round() as i64)
+    }
+
+    pub fn as_secs_f64(self) -> f64 {
+        self.0 as f64 / Self::PER_SECOND as f64
+    }
+
+    /// Time of the sample `index` of a capture running at `rate` samples per second.
+    ///
+    /// Integer rates are computed exactly, not by accumulating the sample period.
+    pub fn from_sample(index: i64, rate: f64) -> Self {
+        if rate.fract() == 0. && rate >= 1. && rate < i64::MAX as f64 {
+            let ps = i128::from(index) * i128::from(Self::PER_SECOND) / rate as i128;
+            Self(ps.clamp(i64::MIN.into(), i64::MAX.into()) as i64)
+        } else {
+            Self::from_secs_f64(index as f64 / rate)
+        }
+    }
+
+    /// Converts a count of `1 / den` seconds, e.g. `den = 1_000_000_000` for nanoseconds.
+    pub fn from_fraction(count: i64, num: i64, den: i64) -> Self {
+        let ps =
+            i128::from(count) * i128::from(num) * i128::from(Self::PER_SECOND) / i128::from(den);
+        Self(ps.clamp(i64::MIN.into(), i64::MAX.into()) as i64)
+    }
+}
+
+impl Add for Timestamp {
+    type Output = Timestamp;
+    fn add(self, other: Timestamp) -> Timestamp {
+        Timestamp(self.0.saturating_add(other.0))
+    }
+}
+
+impl AddAssign for Timestamp {
+    fn add_assign(&mut self, other: Timestamp) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for Timestamp {
+    type Output = Timestamp;
+    fn sub(self, other: Timestamp) -> Timestamp {
+        Timestamp(self.0.saturating_sub(other.0))
+    }
+}
+
+impl SubAssign for Timestamp {
+    fn sub_assign(&mut self, other: Timestamp) {
+        *self = *self - other;
+    }
+}
+
+impl Neg for Timestamp {
+    type Output = Timestamp;
+    fn neg(self) -> Timestamp {
+        Timestamp(self.0.saturating_neg())
+    }
+}
+
+impl Mul<i64> for Timestamp {
+    type Output = Timestamp;
+    fn mul(self, factor: i64) -> Timestamp {
+        Timestamp(self.0.saturating_mul(factor))
+    }
+}
+
+impl Div<i64> for Timestamp {
+    type Output = Timestamp;
+    fn div(self, divisor: i64) -> Timestamp {
+        Timestamp(self.0 / divisor)
+    }
+}
+
+/// Formats the time in seconds, with 9 decimals unless a precision is given.
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let precision = f.precision().unwrap_or(9).min(12);
+        let scale = 10i128.pow(12 - precision as u32);
+        let ps = i128::from(self.0);
+        let rounded = (ps.abs() + scale / 2) / scale;
+        let unit = 10i128.pow(precision as u32);
+        let sign = if ps < 0 { "-" } else { "" };
+        let secs = if precision == 0 {
+            format!("{}{}", sign, rounded)
+        } else {
+            let (int, frac) = (rounded / unit, rounded % unit);
+            format!("{}{}.{:0width$}", sign, int, frac, width = precision)
+        };
+        // numbers are right aligned
+        write!(f, "{:>width$}", secs, width = f.width().unwrap_or(0))
+    }
+}
+
+/// Parses a decimal number of units, each being `10^exponent` picoseconds.
+///
+/// Decimals beyond the picosecond are truncated. Numbers in scientific notation go through `f64`.
+fn parse_decimal(value: &str, exponent: u32) -> Option<Timestamp> {
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(value) => (true, value),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let scale = 10i64.pow(exponent);
+    let ps = if value.contains(['e', 'E']) {
+        let value = value.parse::<f64>().ok()?;
+        Timestamp::from_secs_f64(value * scale as f64 / Timestamp::PER_SECOND as f64).0
+    } else {
+        let (int, frac) = value.split_once('.').unwrap_or((value, ""));
+        let all_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+        if (int.is_empty() && frac.is_empty()) || !all_digits(int) || !all_digits(frac) {
+            return None;
+        }
+        let int = if int.is_empty() {
+            0
+        } else {
+            int.parse::<i64>().ok()?
+        };
+        let mut frac = frac.chars().take(exponent as usize).collect::<String>();
+        while frac.len() < exponent as usize {
+            frac.push('0');
+        }
+        let frac = if frac.is_empty() {
+            0
+        } else {
+            frac.parse::<i64>().ok()?
+        };
+        int.checked_mul(scale)?.checked_add(frac)?
+    };
+    Some(Timestamp(if negative { -ps } else { ps }))
+}
+
+/// Parses a time in seconds.
+impl FromStr for Timestamp {
+    type Err = anyhow::Error;
+    fn from_str(value: &str) -> Result<Self> {
+        parse_decimal(value.trim(), 12).ok_or_else(|| anyhow::anyhow!("Invalid time {:?}", value))
+    }
+}
+
+pub type Event = (Timestamp, Result<Box<dyn EventData>>);
 
 pub trait EventIterator: Iterator<Item = Event> {
     fn into_iterator(self: Box<Self>) -> Box<dyn Iterator<Item = Event>>;
     fn event_type(&self) -> std::any::TypeId;
     fn event_type_name(&self) -> &'static str;
 }
+
+/// Type of the events consumed or produced by a stage.
+#[derive(Clone, Copy)]
+pub struct EventType {
+    id: fn() -> TypeId,
+    name: fn() -> &'static str,
+    fields: fn() -> Vec<String>,
+}
+
+impl EventType {
+    pub const fn of<T: JsonSchema + 'static>() -> Self {
+        Self {
+            id: TypeId::of::<T>,
+            name: std::any::type_name::<T>,
+            fields: crate::json::paths::<T>,
+        }
+    }
+
+    /// The type declared by a stage as `id`, if any.
+    pub fn find(id: TypeId) -> Option<Self> {
+        crate::stages::STAGES
+            .iter()
+            .find_map(|stage| match stage.output {
+                Output::Of(event_type) if event_type.id() == id => Some(event_type),
+                _ => None,
+            })
+    }
+
+    pub fn id(&self) -> TypeId {
+        (self.id)()
+    }
+
+    pub fn name(&self) -> &'static str {
+        (self.name)()
+    }
+
+    /// Dotted paths of the fields of the events, see `json::paths`.
+    pub fn fields(&self) -> Vec<String> {
+        (self.fields)()
+    }
+}
+
+/// Events a stage consumes.
+#[derive(Clone, Copy)]
+pub enum Input {
+    /// Sources start a pipeline.
+    None,
+    Any,
+    Of(EventType),
+}
+
+/// Events a stage produces.
+#[derive(Clone, Copy)]
+pub enum Output {
+    /// Filters forward the events of their input.
+    Input,
+    Of(EventType),
+}
+
+/// Declaration of a stage, checked while the pipeline is built so that a wrong chain is rejected
+/// before any sample is read.
+pub struct Stage {
+    pub name: &'static str,
+    pub input: Input,
+    pub output: Output,
+    /// Stage built in front of this one when its input is missing.
+    pub inserts: Option<&'static str>,
+    pub build: fn(&mut Vec<Box<dyn EventIterator>>, &[String]),
+}
+
+impl Stage {
+    pub fn get(name: &str) -> &'static Stage {
+        crate::stages::STAGES
+            .iter()
+            .find(|stage| stage.name == name)
+            .unwrap_or_else(|| panic!("Unknown stage {}", name))
+    }
+
+    pub fn is_source(&self) -> bool {
+        matches!(self.input, Input::None)
+    }
+}
+
+/// Reports an invalid pipeline and exits.
+pub fn fail(message: &str) -> ! {
+    eprintln!("{}: {}", "Error".red().bold(), message);
+    std::process::exit(1);
+}
+
+/// Stages turning `from` events into `to` events, if any.
+fn layers_between(from: TypeId, to: TypeId) -> Option<Vec<&'static str>> {
+    let mut paths = vec![(from, Vec::new())];
+    let mut seen = vec![from];
+    while !paths.is_empty() {
+        let mut next = Vec::new();
+        for (id, path) in paths {
+            if id == to {
+                return Some(path);
+            }
+            for stage in crate::stages::STAGES.iter() {
+                if let (Input::Of(input), Output::Of(output)) = (stage.input, stage.output) {
+                    if input.id() == id && !seen.contains(&output.id()) {
+                        seen.push(output.id());
+                        let mut path = path.clone();
+                        path.push(stage.name);
+                        next.push((output.id(), path));
+                    }
+                }
+            }
+        }
+        paths = next;
+    }
+    None
+}
+
+/// Pops the input of a stage and checks it against the declaration of the stage.
+///
+/// When the last node produces other events, the layers declared by the stage are inserted first,
+/// `forward` holding the options they share with it.
+pub fn pop_input(
+    pipeline: &mut Vec<Box<dyn EventIterator>>,
+    name: &str,
+    forward: &[String],
+) -> Box<dyn EventIterator> {
+    let stage = Stage::get(name);
+    let expected = match stage.input {
+        Input::Of(expected) => expected,
+        _ => {
+            return pipeline.pop().unwrap_or_else(|| {
+                fail(&format!(
+                    "{} has no input, the pipeline must start with a source",
+                    name
+                ))
+            })
+        }
+    };
+
+    let mismatch = |node: &dyn EventIterator| node.event_type() != expected.id();
+    if let (Some(inserted), Some(true)) = (stage.inserts, pipeline.last().map(|n| mismatch(&**n))) {
+        // keep the inserted layers quiet
+        let forward = forward
+            .iter()
+            .filter(|arg| *arg != "-v" && *arg != "--verbose")
+            .cloned()
+            .collect::<Vec<_>>();
+        (Stage::get(inserted).build)(pipeline, &forward);
+    }
+
+    match pipeline.pop() {
+        None => fail(&format!(
+            "{} has no input, it expects {}",
+            name,
+            expected.name()
+        )),
+        Some(node) if mismatch(&*node) => {
+            let hint = match layers_between(node.event_type(), expected.id()) {
+                Some(layers) => format!("insert `{}` before `{}`", layers.join(" "), name),
+                None => format!("no stage turns them into {}", expected.name()),
+            };
+            fail(&format!(
+                "{} expects {} but got {}: {}",
+                name,
+                expected.name(),
+                node.event_type_name(),
+                hint
+            ))
+        }
+        Some(node) => node,
+    }
+}
+
+/// Parses a time in seconds, optionally suffixed by its unit (`s`, `ms`, `us`, `ns` or `ps`).
+pub fn parse_time(value: &str) -> Result<Timestamp> {
+    let value = value.trim();
+    let (number, exponent) = [("ps", 0), ("ns", 3), ("us", 6), ("ms", 9), ("s", 12)]
+        .iter()
+        .find_map(|(unit, exponent)| Some((value.strip_suffix(unit)?, *exponent)))
+        .unwrap_or((value, 12));
+    parse_decimal(number.trim(), exponent)
+        .ok_or_else(|| anyhow::anyhow!("Invalid time {:?}", value))
+}
+
+#[cfg(test)]
+mod test {
+    use std::any::TypeId;
+
+    use super::{layers_between, parse_time, Timestamp};
+    use crate::source::Sample;
+    use crate::usb::packet::Packet;
+
+    #[test]
+    fn parses_and_formats_times() {
+        assert_eq!(
+            Timestamp::from_ps(1_500_000_000),
+            parse_time("1.5ms").unwrap()
+        );
+        assert_eq!(Timestamp::from_ps(-20), parse_time("-0.02ns").unwrap());
+        assert_eq!(Timestamp::from_ps(1_000), parse_time("1e-9").unwrap());
+        assert_eq!(Timestamp::from_ps(2), "0.0000000000025".parse().unwrap());
+        assert!(parse_time("fast").is_err());
+        assert!(parse_time(".").is_err());
+
+        let ts = Timestamp::from_ps(-1_234_567_890_123);
+        assert_eq!("-1.234567890", ts.to_string());
+        assert_eq!("-1.234567890123", format!("{:.12}", ts));
+        assert_eq!("-1.23", format!("{:.2}", ts));
+        assert_eq!(
+            Timestamp::from_ps(2_000_000_000_000_000),
+            Timestamp::from_sample(1_000_000_000_000, 500e6)
+        );
+    }
+
+    #[test]
+    fn finds_the_missing_layers() {
+        assert_eq!(
+            Some(vec!["usb::signal", "usb::byte", "usb::packet"]),
+            layers_between(TypeId::of::<Sample>(), TypeId::of::<Packet>())
+        );
+        assert_eq!(
+            None,
+            layers_between(TypeId::of::<Packet>(), TypeId::of::<Sample>())
+        );
+    }
+}
@@ -0,0 +1,213 @@
+//! Rewrites the channels of the samples: combines two sources, permutes and inverts channels.
+
+use std::any::TypeId;
+
+use anyhow::Context;
+use clap::{value_t, Arg};
+use itertools::Itertools;
+
+use crate::pipeline::{self, Event, EventIterator};
+use crate::source::{channels, Bits, Sample};
+
+/// Merges the samples of two sources, the channels of the second one being moved by `shift`.
+///
+/// The combined samples start once both sources produced their first sample. Changes of both
+/// sources at the same time are merged in a single sample.
+pub struct Combine {
+    it: std::iter::Peekable<Box<dyn Iterator<Item = (usize, Event)>>>,
+    shift: usize,
+    states: [Option<Sample>; 2],
+}
+
+impl Combine {
+    pub fn new(
+        first: Box<dyn Iterator<Item = Event>>,
+        second: Box<dyn Iterator<Item = Event>>,
+        shift: usize,
+    ) -> Self {
+        let it: Box<dyn Iterator<Item = _>> = Box::new(
+            first
+                .map(|ev| (0, ev))
+                .merge_by(second.map(|ev| (1, ev)), |(_, (a, _)), (_, (b, _))| a <= b),
+        );
+        Self {
+            it: it.peekable(),
+            shift,
+            states: [None, None],
+        }
+    }
+
+    fn combined(&self) -> Option<Sample> {
+        let (first, second) = (self.states[0].as_ref()?, self.states[1].as_ref()?);
+        let merge = |a: &Bits, b: &Bits| {
+            let mut bits = a.clone();
+            bits.truncate(self.shift);
+            bits |= &b.shifted(self.shift);
+            bits
+        };
+        Some(Sample {
+            levels: merge(&first.levels, &second.levels),
+            unknown: merge(&first.unknown, &second.unknown),
+            high_z: merge(&first.high_z, &second.high_z),
+        })
+    }
+}
+
+impl Iterator for Combine {
+    type Item = Event;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (source, (ts, ev)) = self.it.next()?;
+            match ev {
+                Ok(ev) => self.states[source] = Some(*pipeline::downcast::<Sample>(ev)),
+                Err(e) => return Some((ts, Err(e))),
+            }
+            while let Some((source, (_, Ok(ev)))) =
+                self.it.next_if(|(_, (next, ev))| *next == ts && ev.is_ok())
+            {
+                self.states[source] = Some(*pipeline::downcast::<Sample>(ev));
+            }
+            if let Some(sample) = self.combined() {
+                return Some((ts, Ok(Box::new(sample))));
+            }
+        }
+    }
+}
+
+/// Moves and inverts channels.
+pub struct Remap<T> {
+    it: T,
+    /// Destination and source of the moved channels.
+    map: Vec<(usize, usize)>,
+    invert: Bits,
+}
+
+impl<T> Remap<T> {
+    pub fn new(input: T, map: Vec<(usize, usize)>, invert: Bits) -> Self {
+        Self {
+            it: input,
+            map,
+            invert,
+        }
+    }
+
+    fn remap(&self, sample: Sample) -> Sample {
+        let mut out = sample.clone();
+        for &(to, from) in &self.map {
+            for (out, input) in [
+                (&mut out.levels, &sample.levels),
+                (&mut out.unknown, &sample.unknown),
+                (&mut out.high_z, &sample.high_z),
+            ] {
+                out.set(to, input.get(from));
+            }
+        }
+        out.levels ^= &self.invert;
+        out
+    }
+}
+
+impl<T> Iterator for Remap<T>
+where
+    T: Iterator<Item = Event>,
+{
+    type Item = Event;
+    fn next(&mut self) -> Option<Self::Item> {
+        let (ts, ev) = self.it.next()?;
+        let res = ev.map(|ev| Box::new(self.remap(*pipeline::downcast::<Sample>(ev))) as _);
+        Some((ts, res))
+    }
+}
+
+impl<T: 'static + Iterator<Item = Event>> EventIterator for Remap<T> {
+    fn into_iterator(self: Box<Self>) -> Box<dyn Iterator<Item = Event>> {
+        self
+    }
+    fn event_type(&self) -> TypeId {
+        TypeId::of::<Sample>()
+    }
+    fn event_type_name(&self) -> &'static str {
+        std::any::type_name::<Sample>()
+    }
+}
+
+pub fn build(pipeline: &mut Vec<Box<dyn EventIterator>>, args: &[String]) {
+    let arg_matches = clap::SubCommand::with_name("remap")
+        .setting(clap::AppSettings::NoBinaryName)
+        .args(&[
+            Arg::from_usage("-v, --verbose verbose 'set to print events to stdout.'"),
+            Arg::from_usage(
+                "--combine [bit] 'Combines the samples of the last two sources, moving the channels of the last one to start at this bit.'",
+            ),
+            Arg::from_usage("-m, --map [to=from]... 'Moves a channel, the others are left in place.'")
+                .number_of_values(1),
+            Arg::from_usage("-i, --invert [channel]... 'Inverts a channel, after the moves.'")
+                .number_of_values(1),
+        ])
+        .get_matches_from(args);
+
+    // combining must happen first so that the names of the last source are moved
+    if arg_matches.is_present("combine") {
+        let shift = value_t!(arg_matches, "combine", usize).unwrap_or_else(|e| e.exit());
+        let second = pipeline::pop_input(pipeline, "remap", &[]).into_iterator();
+        let first = pipeline::pop_input(pipeline, "remap", &[]).into_iterator();
+        channels::shift_last_source(shift);
+        pipeline.push(Box::new(Remap::new(
+            Combine::new(first, second, shift),
+            Vec::new(),
+            Bits::default(),
+        )));
+    }
+
+    let map = arg_matches
+        .values_of("map")
+        .into_iter()
+        .flatten()
+        .map(|map| {
+            let (to, from) = map
+                .split_once('=')
+                .with_context(|| format!("Invalid map {:?}, expected <to>=<from>", map))?;
+            let channel = |c: &str| {
+                channels::resolve(c.trim())
+                    .with_context(|| format!("Unknown channel {:?} in {:?}", c, map))
+            };
+            Ok((channel(to)?, channel(from)?))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()
+        .unwrap_or_else(|e| clap::Error::value_validation_auto(e.to_string()).exit());
+    let invert = channels::values_of(&arg_matches, "invert")
+        .map_or_else(Bits::default, |channels| channels.into_iter().collect());
+
+    let node = pipeline::pop_input(pipeline, "remap", &[]);
+    let node = Box::new(Remap::new(node.into_iterator(), map, invert));
+    pipeline.push(node);
+}
+
+#[cfg(test)]
+mod test {
+    use crate::source::Bits;
+    use crate::test_util::{levels, samples};
+
+    #[test]
+    fn swaps_and_inverts_channels() {
+        let remap = super::Remap::new(
+            samples(&[(0., 0b0001), (1., 0b0110)]),
+            vec![(0, 1), (1, 0)],
+            Bits::from(0b1000),
+        );
+        assert_eq!(vec![(0., 0b1010), (1., 0b1101)], levels(remap));
+    }
+
+    #[test]
+    fn combines_two_sources() {
+        let combine = super::Combine::new(
+            samples(&[(0., 0b01), (2., 0b10), (3., 0b11)]),
+            samples(&[(1., 0b1), (3., 0b0)]),
+            63,
+        );
+        assert_eq!(
+            vec![(1., 1 << 63 | 0b01), (2., 1 << 63 | 0b10), (3., 0b11)],
+            levels(combine)
+        );
+    }
+}
@@ -1,9 +1,13 @@
-use crate::source::Sample;
-use clap::{value_t, App, Arg, ArgMatches, SubCommand};
+use crate::pipeline::{self, Event, EventIterator, Timestamp};
+use crate::source::{channels, Sample};
+use clap::{value_t, Arg, ArgMatches};
+use schemars::JsonSchema;
+use serde::Serialize;
 use std::fmt;
 use std::str::FromStr;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Serialize, JsonSchema)]
+#[serde(tag = "kind", content = "value")]
 pub enum SerialEvent {
     Rx(u8),
     Tx(u8),
@@ -24,12 +28,11 @@ impl fmt::Debug for SerialEvent {
         }
     }
 }
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, JsonSchema)]
 pub enum SerialError {
     /// Generated when a framing error is detected
     Framing,
     /// Generated when a parity error is detected
-    #[allow(dead_code)]
     Parity,
 }
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -44,6 +47,17 @@ enum Parity {
 enum ParityParseError {
     InvalidInput,
 }
+impl Parity {
+    /// Level of the parity bit expected for `data`.
+    fn bit(self, data: u8) -> bool {
+        match self {
+            Parity::Even => data.count_ones() % 2 == 1,
+            Parity::Odd => !Parity::Even.bit(data),
+            Parity::Set => true,
+            Parity::Clear | Parity::None => false,
+        }
+    }
+}
 impl FromStr for Parity {
     type Err = ParityParseError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -63,14 +77,15 @@ enum MonitorState {
     Start,
     Data(u8, u32),
     Parity(u8),
-    Stop(u8),
+    /// The data and whether its parity bit was right.
+    Stop(u8, bool),
 }
 struct Monitor {
     state: MonitorState,
-    ts: f64,
+    ts: Timestamp,
     data: bool,
     last_fc: bool,
-    bit_duration: f64,
+    bit_duration: Timestamp,
     parity: Parity,
     on_data: &'static dyn Fn(u8) -> SerialEvent,
     on_err: &'static dyn Fn(SerialError) -> SerialEvent,
@@ -86,17 +101,22 @@ impl Monitor {
     ) -> Self {
         Monitor {
             state: MonitorState::Idle,
-            ts: -0.1,
+            ts: Timestamp::MIN,
             data: true,
             last_fc: false,
-            bit_duration: 1. / baud,
+            bit_duration: Timestamp::from_secs_f64(1. / baud),
             parity,
             on_data,
             on_err,
             on_fc,
         }
     }
-    fn update(&mut self, ts: f64, data: bool, fc: bool) -> [Option<(f64, SerialEvent)>; 2] {
+    fn update(
+        &mut self,
+        ts: Timestamp,
+        data: bool,
+        fc: bool,
+    ) -> [Option<(Timestamp, SerialEvent)>; 2] {
         let mut res = [None, None];
         if self.last_fc != fc {
             self.last_fc = fc;
@@ -107,8 +127,8 @@ impl Monitor {
             let (new_ts, new_state) = match self.state {
                 MonitorState::Idle if !data => (ts, MonitorState::Start),
                 MonitorState::Idle => (ts, MonitorState::Idle),
-                MonitorState::Start if (self.ts + self.bit_duration * 1.5) < ts => (
-                    self.ts + self.bit_duration * 1.5,
+                MonitorState::Start if (self.ts + self.bit_duration * 3 / 2) < ts => (
+                    self.ts + self.bit_duration * 3 / 2,
                     MonitorState::Data(if self.data { 0x80 } else { 0 }, 1),
                 ),
                 MonitorState::Data(mut reg, mut shift) if (self.ts + self.bit_duration) < ts => {
@@ -123,17 +143,22 @@ impl Monitor {
                             if self.parity != Parity::None {
                                 MonitorState::Parity(reg)
                             } else {
-                                MonitorState::Stop(reg)
+                                MonitorState::Stop(reg, true)
                             }
                         } else {
                             MonitorState::Data(reg, shift)
                         },
                     )
                 }
-                MonitorState::Parity(_) => unimplemented!(),
-                MonitorState::Stop(reg) if (self.ts + self.bit_duration) < ts => {
+                MonitorState::Parity(reg) if (self.ts + self.bit_duration) < ts => (
+                    self.ts + self.bit_duration,
+                    MonitorState::Stop(reg, self.data == self.parity.bit(reg)),
+                ),
+                MonitorState::Stop(reg, parity) if (self.ts + self.bit_duration) < ts => {
                     if !self.data {
                         res[0] = Some((self.ts, (self.on_err)(SerialError::Framing)));
+                    } else if !parity {
+                        res[0] = Some((self.ts, (self.on_err)(SerialError::Parity)));
                     } else {
                         res[0] = Some((self.ts, (self.on_data)(reg)));
                     }
@@ -153,13 +178,14 @@ impl Monitor {
         self.data = data;
         res
     }
-    fn finalize(&mut self) -> Option<(f64, SerialEvent)> {
+    fn finalize(&mut self) -> Option<(Timestamp, SerialEvent)> {
         let res = match self.state {
             MonitorState::Idle => None,
             MonitorState::Start | MonitorState::Data(_, _) | MonitorState::Parity(_) => {
                 Some((self.ts, (self.on_err)(SerialError::Framing)))
             }
-            MonitorState::Stop(byte) => Some((self.ts, (self.on_data)(byte))),
+            MonitorState::Stop(_, false) => Some((self.ts, (self.on_err)(SerialError::Parity))),
+            MonitorState::Stop(byte, true) => Some((self.ts, (self.on_data)(byte))),
         };
         self.state = MonitorState::Idle;
         res
@@ -168,35 +194,51 @@ impl Monitor {
 
 pub struct Serial<T> {
     it: T,
-    pending_event: Vec<(f64, SerialEvent)>,
+    pending_event: Vec<(Timestamp, SerialEvent)>,
 
     // Monitor Rx + RTS
-    rx_mask: u64,
-    rts_mask: u64,
+    rx_channel: usize,
+    rts_channel: Option<usize>,
     rx: Monitor,
     // Monitor Tx + CTS
-    tx_mask: u64,
-    cts_mask: u64,
+    tx_channel: usize,
+    cts_channel: Option<usize>,
     tx: Monitor,
 }
 
+/// Undefined (unknown or floating) lines are considered idle.
+fn line_level(smp: &Sample, channel: Option<usize>) -> bool {
+    channel
+        .and_then(|channel| smp.level(channel))
+        .unwrap_or(true)
+}
+
 impl<T> Iterator for Serial<T>
 where
-    T: Iterator<Item = (f64, anyhow::Result<Sample>)>,
+    T: Iterator<Item = Event>,
 {
-    type Item = (f64, anyhow::Result<SerialEvent>);
+    type Item = Event;
     fn next(&mut self) -> Option<Self::Item> {
-        while self.pending_event.len() == 0 {
-            let (ts, smp) = match self.it.next()? {
-                (ts, Ok(Sample(smp))) => (ts, smp),
-                (ts, Err(e)) => return Some((ts, Err(e))),
+        while self.pending_event.is_empty() {
+            let (ts, smp) = match self.it.next() {
+                Some((ts, Ok(ev))) => (ts, *pipeline::downcast::<Sample>(ev)),
+                Some((ts, Err(e))) => return Some((ts, Err(e))),
+                None => {
+                    // flush any partially received frame
+                    self.pending_event.extend(self.tx.finalize());
+                    self.pending_event.extend(self.rx.finalize());
+                    if self.pending_event.is_empty() {
+                        return None;
+                    }
+                    break;
+                }
             };
             self.pending_event.extend(
                 self.rx
                     .update(
                         ts,
-                        (smp & self.rx_mask) == self.rx_mask,
-                        (smp & self.rts_mask) == self.rts_mask,
+                        line_level(&smp, Some(self.rx_channel)),
+                        line_level(&smp, self.rts_channel),
                     )
                     .iter()
                     .flatten(),
@@ -205,53 +247,27 @@ where
                 self.tx
                     .update(
                         ts,
-                        (smp & self.tx_mask) == self.tx_mask,
-                        (smp & self.cts_mask) == self.cts_mask,
+                        line_level(&smp, Some(self.tx_channel)),
+                        line_level(&smp, self.cts_channel),
                     )
                     .iter()
                     .flatten(),
             );
-            if self.pending_event.len() == 0 {
-                if let Some(tx) = self.tx.finalize() {
-                    self.pending_event.push(tx);
-                }
-                if let Some(rx) = self.rx.finalize() {
-                    self.pending_event.push(rx);
-                }
-            }
-            self.pending_event
-                .sort_unstable_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
         }
-        self.pending_event.pop().map(|(ts, ev)| (ts, Ok(ev)))
+        self.pending_event
+            .sort_unstable_by_key(|&(ts, _)| std::cmp::Reverse(ts));
+        self.pending_event
+            .pop()
+            .map(|(ts, ev)| (ts, Ok(Box::new(ev) as _)))
     }
 }
 
 impl<T> Serial<T> {
-    pub fn new<'a>(input: T, matches: &ArgMatches<'a>) -> Serial<T> {
-        let tx_mask = 1 << value_t!(matches, "tx", u8).unwrap_or_else(|e| e.exit());
-        let rx_mask = 1 << value_t!(matches, "rx", u8).unwrap_or_else(|e| e.exit());
-        let rts_mask = if let Some(v) = matches.value_of("rts") {
-            match v.parse::<u8>() {
-                Ok(val) => 1 << val,
-                Err(_) => ::clap::Error::value_validation_auto(
-                    "the argument 'rts' isn't a valid value".to_string(),
-                )
-                .exit(),
-            }
-        } else {
-            0
-        };
-        let cts_mask = if let Some(v) = matches.value_of("cts") {
-            match v.parse::<u8>() {
-                Ok(val) => 1 << val,
-                Err(_) => ::clap::Error::value_validation_auto(
-                    "the argument 'cts' isn't a valid value".to_string(),
-                )
-                .exit(),
-            }
-        } else {
-            0
-        };
+    pub fn new(input: T, matches: &ArgMatches<'_>) -> Serial<T> {
+        let tx_channel = channels::value_of(matches, "tx").unwrap();
+        let rx_channel = channels::value_of(matches, "rx").unwrap();
+        let rts_channel = channels::value_of(matches, "rts");
+        let cts_channel = channels::value_of(matches, "cts");
         let baud = if let Some(baud) = matches.value_of("baud") {
             if baud == "auto" {
                 ::clap::Error::with_description(
@@ -276,8 +292,8 @@ impl<T> Serial<T> {
         Self {
             it: input,
             pending_event: Vec::with_capacity(4),
-            rx_mask,
-            rts_mask,
+            rx_channel,
+            rts_channel,
             rx: Monitor::new(
                 baud,
                 parity,
@@ -285,8 +301,8 @@ impl<T> Serial<T> {
                 &SerialEvent::RxError,
                 &SerialEvent::Rts,
             ),
-            tx_mask,
-            cts_mask,
+            tx_channel,
+            cts_channel,
             tx: Monitor::new(
                 baud,
                 parity,
@@ -297,15 +313,21 @@ impl<T> Serial<T> {
         }
     }
 }
-pub trait SerialIteratorExt: Sized {
-    fn into_serial(self, matches: &ArgMatches) -> Serial<Self> {
-        Serial::new(self, matches)
+impl<T: 'static + Iterator<Item = Event>> EventIterator for Serial<T> {
+    fn into_iterator(self: Box<Self>) -> Box<dyn Iterator<Item = Event>> {
+        self
+    }
+    fn event_type(&self) -> std::any::TypeId {
+        std::any::TypeId::of::<SerialEvent>()
+    }
+    fn event_type_name(&self) -> &'static str {
+        std::any::type_name::<SerialEvent>()
     }
 }
-impl<T> SerialIteratorExt for T where T: Iterator<Item = (f64, anyhow::Result<Sample>)> {}
 
 pub fn args() -> [Arg<'static, 'static>; 7] {
     [
+        Arg::from_usage("-v, --verbose verbose 'set to print events to stdout.'"),
         Arg::from_usage("--tx [tx] 'Channel used for the tx pin'").default_value("0"),
         Arg::from_usage("--rx [rx] 'Channel used for the rx pin'").default_value("1"),
         Arg::from_usage("--rts [rts] 'Channel used for the rts pin'"),
@@ -314,10 +336,55 @@ pub fn args() -> [Arg<'static, 'static>; 7] {
         Arg::from_usage("-p --parity [parity] 'Serial line parity'")
             .possible_values(&["even", "odd", "clear", "set", "none"])
             .default_value("none"),
-        Arg::from_usage("-s --stop [stop] 'Serial line stop bit length'").default_value("1"),
     ]
 }
 
-pub fn subcommand() -> App<'static, 'static> {
-    SubCommand::with_name("serial").args(&args())
+pub fn build(pipeline: &mut Vec<Box<dyn EventIterator>>, args: &[String]) {
+    use clap::SubCommand;
+    let arg_matches = SubCommand::with_name("serial")
+        .setting(clap::AppSettings::NoBinaryName)
+        .args(&self::args())
+        .get_matches_from(args);
+
+    let it = pipeline::pop_input(pipeline, "serial", &[]).into_iterator();
+    let node = Box::new(Serial::new(it, &arg_matches));
+    pipeline.push(node);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Monitor, Parity, SerialEvent};
+    use crate::pipeline::Timestamp;
+
+    /// Decodes a frame of `A` (0x41) at 1 baud, sampled every quarter of a bit.
+    fn decode(parity: Parity, parity_bit: bool) -> Vec<String> {
+        let mut frame = vec![true, true, false];
+        frame.extend((0..8).map(|bit| 0x41 & (1 << bit) != 0));
+        frame.extend([parity_bit, true, true, true]);
+        let mut monitor = Monitor::new(
+            1.,
+            parity,
+            &SerialEvent::Rx,
+            &SerialEvent::RxError,
+            &SerialEvent::Rts,
+        );
+        (0..frame.len() * 4)
+            .flat_map(|i| {
+                let ts = Timestamp::from_secs_f64(i as f64 / 4.);
+                let [data, _] = monitor.update(ts, frame[i / 4], false);
+                data
+            })
+            .map(|(_, ev)| format!("{:?}", ev))
+            .collect()
+    }
+
+    #[test]
+    fn checks_the_parity_bit() {
+        assert_eq!(vec!["Rx('A')"], decode(Parity::Even, false));
+        assert_eq!(vec!["RxError(Parity)"], decode(Parity::Even, true));
+        assert_eq!(vec!["Rx('A')"], decode(Parity::Odd, true));
+        assert_eq!(vec!["RxError(Parity)"], decode(Parity::Odd, false));
+        assert_eq!(vec!["Rx('A')"], decode(Parity::Set, true));
+        assert_eq!(vec!["RxError(Parity)"], decode(Parity::Clear, true));
+    }
 }
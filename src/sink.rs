@@ -1,16 +1,603 @@
-use anyhow::Error;
+//! Output of the decoded events.
+//!
+//! The events of the last stage, and those of the stages given `-v`, all go through a single sink
+//! chosen with `--sink`, so that every layer is written in the same format.
 
-struct PrintSink<T>(T);
+use std::any::TypeId;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, BufWriter, Write};
 
-impl<T: Iterator<Item = (f64, Result<Box<dyn std::fmt::Debug>, Error>)>> Iterator for PrintSink<T> {
-    type Item = ();
+use colored::*;
+use serde_json::{json, Map, Value};
+
+use crate::json;
+use crate::pipeline::{self, Event, EventData, EventIterator, EventType, Timestamp};
+
+pub trait Sink {
+    /// Announces the type of the events of a layer, before any event is written.
+    fn declare(&mut self, _event_type: EventType) {}
+    /// Writes an event produced by the stage `layer`.
+    fn write(&mut self, layer: &str, event: &Event);
+    /// Called once the pipeline is drained.
+    fn finish(&mut self) {}
+}
+
+/// Custom sinks.
+impl<F: FnMut(&str, &Event)> Sink for F {
+    fn write(&mut self, layer: &str, event: &Event) {
+        self(layer, event)
+    }
+}
+
+/// Ends the process quietly when the reader of the output went away (e.g. `| head`).
+fn check(res: io::Result<()>) {
+    match res {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => std::process::exit(0),
+        Err(e) => pipeline::fail(&format!("Writing the events: {}", e)),
+    }
+}
+
+/// Writes the events as text.
+pub struct PrintSink<W: Write> {
+    out: W,
+}
+
+impl<W: Write> PrintSink<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+}
+
+impl<W: Write> Sink for PrintSink<W> {
+    fn write(&mut self, _layer: &str, (ts, res): &Event) {
+        check(match res {
+            Ok(ev) => writeln!(self.out, "{:.9}: {:?}", ts, ev),
+            Err(e) => writeln!(self.out, "{:.9}: Error: {:#}", ts, e),
+        });
+    }
+    fn finish(&mut self) {
+        check(self.out.flush());
+    }
+}
+
+/// Writes the events as text, with the name of their layer in a color of its own.
+pub struct ColorSink<W: Write> {
+    out: W,
+    colors: HashMap<String, Color>,
+}
+
+impl<W: Write> ColorSink<W> {
+    const COLORS: [Color; 5] = [
+        Color::Green,
+        Color::Cyan,
+        Color::Yellow,
+        Color::Magenta,
+        Color::Blue,
+    ];
+
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            colors: HashMap::new(),
+        }
+    }
+}
+
+impl<W: Write> Sink for ColorSink<W> {
+    fn write(&mut self, layer: &str, (ts, res): &Event) {
+        let count = self.colors.len();
+        let color = *self
+            .colors
+            .entry(layer.to_owned())
+            .or_insert(Self::COLORS[count % Self::COLORS.len()]);
+        let layer = layer.color(color).bold();
+        check(match res {
+            Ok(ev) => writeln!(self.out, "{:10.9}: {}: {:?}", ts, layer, ev),
+            Err(e) => writeln!(
+                self.out,
+                "{:10.9}: {}: {}: {:#}",
+                ts,
+                layer,
+                "Error".red().bold(),
+                e
+            ),
+        });
+    }
+    fn finish(&mut self) {
+        check(self.out.flush());
+    }
+}
+
+/// The type of an event and its fields.
+fn fields(ev: &dyn EventData) -> (Value, Map<String, Value>) {
+    let mut fields = json::object(ev.to_json());
+    // events of other layers carry their own type
+    let event_type = fields
+        .remove("type")
+        .unwrap_or_else(|| json!(json::type_name(ev.type_name())));
+    (event_type, fields)
+}
+
+/// Writes the events as JSON Lines, e.g.
+/// `{"ts":0.5,"ts_ps":500000000000,"layer":"serial","type":"serial::SerialEvent","kind":"Rx","value":65}`.
+///
+/// The timestamp is given in seconds by `ts` and exactly, in picoseconds, by `ts_ps`. The fields
+/// of the events follow `layer` and `type`. Errors are written with the type `error`, their
+/// message in `error` and the errors that caused it in `causes`.
+pub struct JsonSink<W: Write> {
+    out: W,
+}
+
+impl<W: Write> JsonSink<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+}
+
+impl<W: Write> Sink for JsonSink<W> {
+    fn write(&mut self, layer: &str, (ts, res): &Event) {
+        let (event_type, fields) = match res {
+            Ok(ev) => fields(ev.as_ref()),
+            Err(e) => {
+                let causes = e.chain().skip(1).map(|cause| cause.to_string());
+                let fields =
+                    json!({ "error": e.to_string(), "causes": causes.collect::<Vec<_>>() });
+                (json!("error"), json::object(fields))
+            }
+        };
+        let mut line = format!(
+            "{{\"ts\":{},\"ts_ps\":{},\"layer\":{},\"type\":{}",
+            json!(ts.as_secs_f64()),
+            ts.as_ps(),
+            json!(layer),
+            event_type
+        );
+        for (key, value) in fields {
+            line += &format!(",{}:{}", Value::String(key), value);
+        }
+        check(writeln!(self.out, "{}}}", line));
+    }
+    fn finish(&mut self) {
+        check(self.out.flush());
+    }
+}
+
+/// Writes the events as CSV, one row per event with the columns of `CsvSink::HEADER` followed by
+/// the fields of the event types declared by the layers, flattened into dotted paths (e.g.
+/// `value.pid`).
+///
+/// Addresses, endpoints and data share the `address`, `endpoint` and `data` columns whatever the
+/// type of their event, byte buffers being hex strings. The duration of an event lasts until the
+/// next event of its layer, so each row is written once the following event of its layer arrived,
+/// the last ones when the pipeline is drained.
+pub struct CsvSink<W: Write> {
+    out: W,
+    /// Columns of the event fields, after those of `HEADER`, by their path.
+    columns: HashMap<String, usize>,
+    /// Whether the header was written, past which no type can be declared.
+    started: bool,
+    /// Last row of each layer waiting for its duration, along with the rank of its event.
+    pending: HashMap<String, (usize, Timestamp, Vec<String>)>,
+    count: usize,
+}
+
+impl<W: Write> CsvSink<W> {
+    pub const HEADER: [&'static str; 9] = [
+        "timestamp",
+        "duration",
+        "layer",
+        "type",
+        "kind",
+        "address",
+        "endpoint",
+        "data",
+        "error",
+    ];
+
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            columns: HashMap::new(),
+            started: false,
+            pending: HashMap::new(),
+            count: 0,
+        }
+    }
+
+    fn column(name: &str) -> usize {
+        Self::HEADER
+            .iter()
+            .position(|column| *column == name)
+            .unwrap()
+    }
+
+    /// Column of the fields shared by the event types, if any.
+    fn shared_column(path: &str) -> Option<usize> {
+        let name = match (path, path.rsplit('.').next().unwrap()) {
+            ("kind" | "error", _) => path,
+            (_, "addr" | "address") => "address",
+            (_, "endpoint") => "endpoint",
+            (_, "data" | "payload") => "data",
+            _ => return None,
+        };
+        Some(Self::column(name))
+    }
+
+    fn row(&self, layer: &str, (ts, res): &Event) -> Vec<String> {
+        let mut row = vec![String::new(); Self::HEADER.len() + self.columns.len()];
+        row[Self::column("timestamp")] = format!("{:.12}", ts);
+        row[Self::column("layer")] = layer.to_owned();
+        let (event_type, fields) = match res {
+            Ok(ev) => fields(ev.as_ref()),
+            Err(e) => (
+                json!("error"),
+                json::object(json!({ "error": format!("{:#}", e) })),
+            ),
+        };
+        row[Self::column("type")] = text(&event_type);
+        let mut flat = Vec::new();
+        for (key, value) in fields {
+            flatten(key, value, &mut flat);
+        }
+        for (path, value) in flat {
+            let column = Self::shared_column(&path)
+                .or_else(|| Some(Self::HEADER.len() + self.columns.get(&path)?));
+            match column {
+                Some(column) if row[column].is_empty() => row[column] = value,
+                Some(column) => {
+                    row[column].push(' ');
+                    row[column] += &value;
+                }
+                // the layers declare the types of their events
+                None => debug_assert!(false, "Undeclared field {} of {}", path, event_type),
+            }
+        }
+        row
+    }
+
+    fn write_header(&mut self) {
+        let mut header = Self::HEADER.map(str::to_owned).to_vec();
+        header.resize(Self::HEADER.len() + self.columns.len(), String::new());
+        for (path, &column) in &self.columns {
+            header[Self::HEADER.len() + column] = path.clone();
+        }
+        self.write_row(&header);
+        self.started = true;
+    }
+
+    fn write_row(&mut self, row: &[String]) {
+        let row = row.iter().map(|cell| quote(cell)).collect::<Vec<_>>();
+        check(writeln!(self.out, "{}", row.join(",")));
+    }
+}
+
+/// Text of a value, without the quotes of strings.
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        value => value.to_string(),
+    }
+}
+
+/// Lists the fields of nested objects by their dotted path.
+fn flatten(path: String, value: Value, flat: &mut Vec<(String, String)>) {
+    match value {
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                flatten(format!("{}.{}", path, key), value, flat);
+            }
+        }
+        Value::Null => {}
+        value => flat.push((path, text(&value))),
+    }
+}
+
+/// Quotes the cells holding separators.
+fn quote(cell: &str) -> Cow<'_, str> {
+    if cell.contains(&[',', '"', '\n', '\r'][..]) {
+        Cow::Owned(format!("\"{}\"", cell.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(cell)
+    }
+}
+
+impl<W: Write> Sink for CsvSink<W> {
+    fn declare(&mut self, event_type: EventType) {
+        debug_assert!(!self.started, "{} declared too late", event_type.name());
+        for path in event_type.fields() {
+            if Self::shared_column(&path).is_none() {
+                let next = self.columns.len();
+                self.columns.entry(path).or_insert(next);
+            }
+        }
+    }
+    fn write(&mut self, layer: &str, event: &Event) {
+        if !self.started {
+            self.write_header();
+        }
+        let row = self.row(layer, event);
+        let ts = event.0;
+        self.count += 1;
+        if let Some((_, previous, mut row)) =
+            self.pending.insert(layer.to_owned(), (self.count, ts, row))
+        {
+            row[Self::column("duration")] = format!("{:.12}", ts - previous);
+            self.write_row(&row);
+        }
+    }
+    fn finish(&mut self) {
+        if !self.started {
+            self.write_header();
+        }
+        let mut pending = self.pending.drain().map(|(_, row)| row).collect::<Vec<_>>();
+        pending.sort_by_key(|(rank, _, _)| *rank);
+        for (_, _, row) in pending {
+            self.write_row(&row);
+        }
+        check(self.out.flush());
+    }
+}
+
+/// Discards the events, the pipeline only being run for its side effects.
+pub struct QuietSink;
+
+impl Sink for QuietSink {
+    fn write(&mut self, _layer: &str, _event: &Event) {}
+}
+
+/// A sink selectable with `--sink`.
+pub struct SinkType {
+    pub name: &'static str,
+    pub build: fn(&[String]) -> Box<dyn Sink>,
+}
+
+/// Checks that no option is given to a sink that has none.
+fn no_options(name: &str, args: &[String]) {
+    clap::SubCommand::with_name(name)
+        .setting(clap::AppSettings::NoBinaryName)
+        .get_matches_from(args);
+}
+
+pub const SINKS: [SinkType; 5] = [
+    SinkType {
+        name: "text",
+        build: |args| {
+            no_options("text", args);
+            Box::new(PrintSink::new(BufWriter::new(io::stdout())))
+        },
+    },
+    SinkType {
+        name: "colored",
+        build: |args| {
+            no_options("colored", args);
+            colored::control::set_override(true);
+            Box::new(ColorSink::new(BufWriter::new(io::stdout())))
+        },
+    },
+    SinkType {
+        name: "json",
+        build: |args| {
+            no_options("json", args);
+            Box::new(JsonSink::new(BufWriter::new(io::stdout())))
+        },
+    },
+    SinkType {
+        name: "csv",
+        build: |args| {
+            no_options("csv", args);
+            Box::new(CsvSink::new(BufWriter::new(io::stdout())))
+        },
+    },
+    SinkType {
+        name: "quiet",
+        build: |args| {
+            no_options("quiet", args);
+            Box::new(QuietSink)
+        },
+    },
+];
+
+/// Builds the sink `name` out of its options.
+pub fn build(name: &str, args: &[String]) -> Box<dyn Sink> {
+    let sink = SINKS
+        .iter()
+        .find(|sink| sink.name == name)
+        .unwrap_or_else(|| {
+            let names = SINKS.iter().map(|sink| sink.name).collect::<Vec<_>>();
+            pipeline::fail(&format!(
+                "Unknown sink {:?}, expected one of {}",
+                name,
+                names.join(", ")
+            ))
+        });
+    (sink.build)(args)
+}
+
+thread_local! {
+    static SINK: RefCell<Box<dyn Sink>> =
+        RefCell::new(Box::new(PrintSink::new(BufWriter::new(io::stdout()))));
+}
+
+/// Sets the sink of the pipeline, text on stdout by default.
+pub fn install(sink: Box<dyn Sink>) {
+    SINK.with(|current| *current.borrow_mut() = sink);
+}
+
+/// Announces the type of the events of a layer, as declared by the stages.
+pub fn declare(event_type: TypeId) {
+    if let Some(event_type) = EventType::find(event_type) {
+        SINK.with(|sink| sink.borrow_mut().declare(event_type));
+    }
+}
+
+pub fn write(layer: &str, event: &Event) {
+    SINK.with(|sink| sink.borrow_mut().write(layer, event));
+}
+
+/// Writes all the events of the last stage of the pipeline.
+pub fn drain(layer: &str, node: Box<dyn EventIterator>) {
+    declare(node.event_type());
+    for event in node.into_iterator() {
+        write(layer, &event);
+    }
+    SINK.with(|sink| sink.borrow_mut().finish());
+}
+
+/// Writes the events of a stage as they go through, for stages given `-v`.
+pub struct Tap {
+    it: Box<dyn Iterator<Item = Event>>,
+    layer: &'static str,
+    event_type: (std::any::TypeId, &'static str),
+}
+
+impl Tap {
+    pub fn new(layer: &'static str, node: Box<dyn EventIterator>) -> Self {
+        let event_type = (node.event_type(), node.event_type_name());
+        declare(event_type.0);
+        Self {
+            it: node.into_iterator(),
+            layer,
+            event_type,
+        }
+    }
+}
+
+impl Iterator for Tap {
+    type Item = Event;
     fn next(&mut self) -> Option<Self::Item> {
-        self.0.next().map(|(ts, res)| {
-            match res {
-                Ok(debug) => println!("{:.9}: {:?}", ts, debug),
-                Err(e) => println!("{:.9}: {:?}", ts, e),
-            };
-            () // make it an explicit `unit` return
-        })
+        let event = self.it.next()?;
+        write(self.layer, &event);
+        Some(event)
+    }
+}
+
+impl EventIterator for Tap {
+    fn into_iterator(self: Box<Self>) -> Box<dyn Iterator<Item = Event>> {
+        self
+    }
+    fn event_type(&self) -> std::any::TypeId {
+        self.event_type.0
+    }
+    fn event_type_name(&self) -> &'static str {
+        self.event_type.1
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CsvSink, JsonSink, PrintSink, Sink};
+    use crate::pipeline::{EventType, Timestamp};
+    use crate::serial::SerialEvent;
+    use crate::spi::SpiEvent;
+    use crate::spif::Command;
+    use crate::usb::protocol::{self, Transaction};
+    use crate::usb::types::{Data, DataPID, HandShake, Token, TokenType};
+
+    #[test]
+    fn prints_events_and_errors() {
+        let mut out = Vec::new();
+        let mut sink = PrintSink::new(&mut out);
+        let ts = Timestamp::from_secs_f64(1.5);
+        sink.write("serial", &(ts, Ok(Box::new(SerialEvent::Rx(b'B')))));
+        sink.write("serial", &(ts, Err(anyhow::anyhow!("Framing error"))));
+        sink.finish();
+        assert_eq!(
+            "1.500000000: Rx('B')\n1.500000000: Error: Framing error\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn writes_json_records() {
+        let mut out = Vec::new();
+        let mut sink = JsonSink::new(&mut out);
+        let ts = Timestamp::from_secs_f64(0.5);
+        sink.write("serial", &(ts, Ok(Box::new(SerialEvent::Rx(b'A')))));
+        let error = anyhow::anyhow!("Framing error").context("Decoding");
+        sink.write("serial", &(ts, Err(error)));
+        sink.finish();
+        assert_eq!(
+            concat!(
+                r#"{"ts":0.5,"ts_ps":500000000000,"layer":"serial","type":"serial::SerialEvent","#,
+                r#""kind":"Rx","value":65}"#,
+                "\n",
+                r#"{"ts":0.5,"ts_ps":500000000000,"layer":"serial","type":"error","#,
+                r#""causes":["Framing error"],"error":"Decoding"}"#,
+                "\n"
+            ),
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn writes_csv_rows() {
+        let mut out = Vec::new();
+        let mut sink = CsvSink::new(&mut out);
+        sink.declare(EventType::of::<Command>());
+        sink.declare(EventType::of::<SpiEvent>());
+        sink.declare(EventType::of::<SerialEvent>());
+        let ts = Timestamp::from_secs_f64;
+        let erase = Command::SectorErase { addr: 0x1000 };
+        sink.write("spif", &(ts(1.), Ok(Box::new(erase))));
+        let data = SpiEvent::Data {
+            mosi: 0x20,
+            miso: 0,
+        };
+        sink.write("spi", &(ts(0.5), Ok(Box::new(data))));
+        sink.write("serial", &(ts(1.5), Ok(Box::new(SerialEvent::Rx(b'A')))));
+        sink.write(
+            "spif",
+            &(ts(2.), Err(anyhow::anyhow!("Unknown command, 1"))),
+        );
+        sink.finish();
+        assert_eq!(
+            concat!(
+                "timestamp,duration,layer,type,kind,address,endpoint,data,error,",
+                "value,value.device_id,value.manufacturer,value.miso,value.mosi\n",
+                "1.000000000000,1.000000000000,spif,spif::Command,SectorErase,4096,,,,,,,,\n",
+                "0.500000000000,,spi,spi::SpiEvent,Data,,,,,,,,0,32\n",
+                "1.500000000000,,serial,serial::SerialEvent,Rx,,,,,65,,,,\n",
+                "2.000000000000,,spif,error,,,,,\"Unknown command, 1\",,,,,\n",
+            ),
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn writes_usb_transactions() {
+        let mut out = Vec::new();
+        let mut sink = CsvSink::new(&mut out);
+        sink.declare(EventType::of::<protocol::Event>());
+        let ts = Timestamp::from_secs_f64;
+        let transaction = Transaction {
+            token: Token {
+                token_type: TokenType::Out,
+                address: 3,
+                endpoint: 1,
+            },
+            data: Some(Data {
+                pid: DataPID::Data0,
+                payload: vec![0xde, 0xad],
+            }),
+            handshake: HandShake::Ack,
+        };
+        let transaction = protocol::Event::Transaction(transaction);
+        sink.write("usb", &(ts(1.), Ok(Box::new(transaction))));
+        sink.write("usb", &(ts(1.25), Ok(Box::new(protocol::Event::Sof(5)))));
+        sink.finish();
+        assert_eq!(
+            concat!(
+                "timestamp,duration,layer,type,kind,address,endpoint,data,error,",
+                "value,value.data.pid,value.handshake,value.token.token_type\n",
+                "1.000000000000,0.250000000000,usb,usb::protocol::Event,Transaction,3,1,dead,,",
+                ",Data0,Ack,Out\n",
+                "1.250000000000,,usb,usb::protocol::Event,Sof,,,,,5,,,\n",
+            ),
+            String::from_utf8(out).unwrap()
+        );
     }
 }
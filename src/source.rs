@@ -1,6 +1,81 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Read};
+
+use anyhow::Context;
+
+mod archive;
+pub mod auto;
+mod bits;
+pub mod channels;
+pub mod csv;
 pub mod logic;
 pub mod logic2;
+pub mod origin;
+pub mod sigrok;
 pub mod vcd;
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub struct Sample(pub u64);
+pub use bits::Bits;
+
+/// Input stream of a source: a file, a named pipe or stdin.
+pub type Input = Box<dyn BufRead>;
+
+/// Opens the input of a source, stdin being used when the file is absent or `-`.
+pub fn open_input(file: Option<&str>) -> anyhow::Result<Input> {
+    Ok(match file {
+        None | Some("-") => Box::new(std::io::stdin().lock()),
+        Some(path) => Box::new(BufReader::new(
+            File::open(path).with_context(|| format!("Opening {:?}", path))?,
+        )),
+    })
+}
+
+/// Reads up to `len` bytes of the input without consuming them.
+///
+/// Pipes may hand their content over in small pieces, so a single `fill_buf` may not be enough to
+/// sniff the input.
+pub fn peek(input: &mut Input, len: usize) -> std::io::Result<Vec<u8>> {
+    let mut head = Vec::new();
+    input.by_ref().take(len as u64).read_to_end(&mut head)?;
+    let rest = std::mem::replace(input, Box::new(std::io::empty()));
+    *input = Box::new(Cursor::new(head.clone()).chain(rest));
+    Ok(head)
+}
+
+/// State of all the channels at a given time.
+///
+/// Channels flagged in `unknown` (`x`) or `high_z` (`z`) do not have a defined level and their bit
+/// in `levels` is meaningless.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, JsonSchema)]
+pub struct Sample {
+    pub levels: Bits,
+    pub unknown: Bits,
+    pub high_z: Bits,
+}
+
+impl Sample {
+    /// Level of a channel, `None` if it is not defined.
+    pub fn level(&self, channel: usize) -> Option<bool> {
+        if self.unknown.get(channel) || self.high_z.get(channel) {
+            None
+        } else {
+            Some(self.levels.get(channel))
+        }
+    }
+}
+
+impl From<u64> for Sample {
+    fn from(levels: u64) -> Self {
+        Bits::from(levels).into()
+    }
+}
+
+impl From<Bits> for Sample {
+    fn from(levels: Bits) -> Self {
+        Self {
+            levels,
+            ..Self::default()
+        }
+    }
+}
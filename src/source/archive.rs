@@ -0,0 +1,128 @@
+//! Streaming access to the entries of zip based session files.
+
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use flate2::read::DeflateDecoder;
+use zip::{CompressionMethod, ZipArchive};
+
+pub const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+#[derive(Debug)]
+pub struct Entry {
+    pub name: String,
+    pub size: u64,
+    compression: CompressionMethod,
+    data_start: u64,
+    compressed_size: u64,
+}
+
+/// Where the archive is read from.
+#[derive(Debug)]
+enum Storage {
+    File(PathBuf),
+    /// Streams can't be seeked and are loaded in memory.
+    Memory(Arc<Vec<u8>>),
+}
+
+/// Part of an archive loaded in memory.
+struct Slice(Arc<Vec<u8>>, usize, usize);
+
+impl AsRef<[u8]> for Slice {
+    fn as_ref(&self) -> &[u8] {
+        &self.0[self.1..self.2]
+    }
+}
+
+/// Entries are opened with their own file handle so that several of them can be read
+/// concurrently without loading them in memory.
+#[derive(Debug)]
+pub struct Archive {
+    storage: Storage,
+    entries: Vec<Entry>,
+}
+
+impl Archive {
+    pub fn open(path: &Path) -> Result<Self> {
+        let entries = Self::read_entries(File::open(path)?)?;
+        Ok(Self {
+            storage: Storage::File(path.to_owned()),
+            entries,
+        })
+    }
+
+    /// Loads an archive from a stream.
+    pub fn from_reader(mut input: impl Read) -> Result<Self> {
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        let entries = Self::read_entries(Cursor::new(&data))?;
+        Ok(Self {
+            storage: Storage::Memory(Arc::new(data)),
+            entries,
+        })
+    }
+
+    fn read_entries(input: impl Read + Seek) -> Result<Vec<Entry>> {
+        let mut zip = ZipArchive::new(input)?;
+        (0..zip.len())
+            .map(|i| {
+                let file = zip.by_index_raw(i)?;
+                Ok(Entry {
+                    name: file.name().to_owned(),
+                    size: file.size(),
+                    compression: file.compression(),
+                    data_start: file.data_start(),
+                    compressed_size: file.compressed_size(),
+                })
+            })
+            .collect()
+    }
+
+    /// Checks for the magic number of zip files.
+    pub fn is_archive(path: &Path) -> Result<bool> {
+        let mut magic = [0; 4];
+        let len = File::open(path)?.read(&mut magic)?;
+        Ok(magic[..len] == *ZIP_MAGIC)
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn entry(&self, name: &str) -> Option<&Entry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    pub fn reader(&self, entry: &Entry) -> Result<Box<dyn Read>> {
+        let raw: Box<dyn Read> = match &self.storage {
+            Storage::File(path) => {
+                let mut file = File::open(path)?;
+                file.seek(SeekFrom::Start(entry.data_start))?;
+                Box::new(file.take(entry.compressed_size))
+            }
+            Storage::Memory(data) => {
+                let start = entry.data_start as usize;
+                let end = start + entry.compressed_size as usize;
+                anyhow::ensure!(end <= data.len(), "Truncated entry {}", entry.name);
+                Box::new(Cursor::new(Slice(data.clone(), start, end)))
+            }
+        };
+        Ok(match entry.compression {
+            CompressionMethod::Stored => Box::new(raw),
+            CompressionMethod::Deflated => Box::new(DeflateDecoder::new(raw)),
+            method => return Err(anyhow!("Unsupported compression method {:?}", method)),
+        })
+    }
+
+    pub fn read_to_string(&self, name: &str) -> Result<String> {
+        let entry = self
+            .entry(name)
+            .ok_or_else(|| anyhow!("Missing {} in the archive", name))?;
+        let mut content = String::new();
+        self.reader(entry)?.read_to_string(&mut content)?;
+        Ok(content)
+    }
+}
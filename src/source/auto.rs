@@ -0,0 +1,186 @@
+//! Picks the source matching the content of the input.
+
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use clap::Arg;
+
+use super::archive::{Archive, ZIP_MAGIC};
+use super::{channels, open_input, origin, peek, Input};
+use crate::pipeline::{self, EventIterator};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Logic,
+    Logic2,
+    Sigrok,
+    Vcd,
+    Csv,
+}
+
+fn detect_content(head: &[u8]) -> Format {
+    let text = String::from_utf8_lossy(head);
+    if head.starts_with(b"<SALEAE>") {
+        Format::Logic2
+    } else if text.contains("$timescale") || text.contains("$var") || text.starts_with('$') {
+        Format::Vcd
+    } else if text.to_lowercase().starts_with("time") && text.lines().next().unwrap().contains(',')
+    {
+        Format::Csv
+    } else {
+        Format::Logic
+    }
+}
+
+fn detect_session(archive: &Archive) -> Result<Format> {
+    if archive.entry("metadata").is_some() {
+        Ok(Format::Sigrok)
+    } else if archive.entry("meta.json").is_some() {
+        Ok(Format::Logic2)
+    } else {
+        Err(anyhow!("Unknown session format"))
+    }
+}
+
+fn detect(path: &Path) -> Result<Format> {
+    if path.is_dir() {
+        let is_export = std::fs::read_dir(path)?.any(|entry| {
+            entry.is_ok_and(|entry| {
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                name.starts_with("digital_") || name.starts_with("analog_")
+            })
+        });
+        anyhow::ensure!(is_export, "{:?} is not a Saleae Logic 2 export", path);
+        return Ok(Format::Logic2);
+    }
+
+    if Archive::is_archive(path)? {
+        return detect_session(&Archive::open(path)?);
+    }
+
+    let mut head = Vec::new();
+    std::fs::File::open(path)?
+        .take(1024)
+        .read_to_end(&mut head)?;
+    Ok(detect_content(&head))
+}
+
+/// Sniffs a stream, leaving its content available to the source.
+fn detect_stream(input: &mut Input) -> Result<Format> {
+    let head = peek(input, 1024)?;
+    if !head.starts_with(ZIP_MAGIC) {
+        return Ok(detect_content(&head));
+    }
+
+    // sessions are loaded in memory anyway
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+    let format = detect_session(&Archive::from_reader(data.as_slice())?)?;
+    *input = Box::new(Cursor::new(data));
+    Ok(format)
+}
+
+pub fn build(pipeline: &mut Vec<Box<dyn EventIterator>>, args: &[String]) {
+    // the arguments belong to the detected source, the options of all the sources are accepted
+    // to find the input among them (those of csv and sigrok being shared with the others)
+    let matches = clap::SubCommand::with_name("auto")
+        .setting(clap::AppSettings::NoBinaryName)
+        .args(&super::logic::options())
+        .args(&super::logic2::options())
+        .args(&super::vcd::options())
+        .arg(Arg::with_name("file").help("Input capture, stdin if absent or '-'."))
+        .args(&channels::args())
+        .args(&origin::args())
+        .get_matches_from(args);
+    let file = matches.value_of("file");
+
+    let (format, input) = match file.map(Path::new) {
+        Some(path) if path.is_dir() || path.is_file() => {
+            let format = detect(path)
+                .with_context(|| format!("Detecting the format of {:?}", path))
+                .unwrap_or_else(|e| pipeline::fail(&format!("{:#}", e)));
+            (format, None)
+        }
+        // stdin and named pipes can't be rewound, the sniffed input is handed over to the source
+        _ => {
+            let mut input =
+                open_input(file).unwrap_or_else(|e| pipeline::fail(&format!("{:#}", e)));
+            let format = detect_stream(&mut input)
+                .context("Detecting the format of the input")
+                .unwrap_or_else(|e| pipeline::fail(&format!("{:#}", e)));
+            (format, Some(input))
+        }
+    };
+
+    build_format(pipeline, format, args, input);
+}
+
+fn build_format(
+    pipeline: &mut Vec<Box<dyn EventIterator>>,
+    format: Format,
+    args: &[String],
+    input: Option<Input>,
+) {
+    match format {
+        Format::Logic => super::logic::build_from(pipeline, args, input),
+        Format::Logic2 => super::logic2::build_from(pipeline, args, input),
+        Format::Sigrok => super::sigrok::build_from(pipeline, args, input),
+        Format::Vcd => super::vcd::build_from(pipeline, args, input),
+        Format::Csv => super::csv::build_from(pipeline, args, input),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::BufReader;
+
+    use super::{detect_content, detect_stream, Format};
+    use crate::source::Input;
+    use crate::test_util::{levels, zip, Trickle};
+
+    /// Reads `-` from a stream handing its data out a few bytes at a time.
+    fn stream(data: Vec<u8>) -> (Format, Vec<(f64, u64)>) {
+        let mut input: Input = Box::new(BufReader::new(Trickle::new(data, 3)));
+        let format = detect_stream(&mut input).unwrap();
+        let mut pipeline = Vec::new();
+        super::build_format(&mut pipeline, format, &["-".to_owned()], Some(input));
+        (format, levels(pipeline.pop().unwrap().into_iterator()))
+    }
+
+    #[test]
+    fn detects_content() {
+        assert_eq!(Format::Logic2, detect_content(b"<SALEAE>\x01\x00\x00\x00"));
+        assert_eq!(
+            Format::Vcd,
+            detect_content(b"$date\n  today\n$end\n$timescale 1ns $end")
+        );
+        assert_eq!(Format::Csv, detect_content(b"Time [s],Channel 0\n0.0,1\n"));
+        assert_eq!(
+            Format::Logic,
+            detect_content(b"\x00\x00\x00\x00\x00\x00\x00\x00\x01")
+        );
+    }
+
+    #[test]
+    fn reads_streams() {
+        let vcd =
+            "$timescale 1us $end\n$var wire 1 ! tx $end\n$enddefinitions $end\n#0\n1!\n#5\n0!\n";
+        assert_eq!(
+            (Format::Vcd, vec![(0., 1), (5e-6, 0)]),
+            stream(vcd.as_bytes().to_vec())
+        );
+
+        let metadata =
+            "[device 1]\ncapturefile=logic-1\ntotal probes=1\nsamplerate=2 Hz\nunitsize=1\n";
+        let session = zip(&[
+            ("metadata", metadata.as_bytes()),
+            ("logic-1", &[0, 1, 1, 0]),
+        ]);
+        assert_eq!(
+            (Format::Sigrok, vec![(0., 0), (0.5, 1), (1.5, 0)]),
+            stream(session)
+        );
+    }
+}
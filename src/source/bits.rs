@@ -0,0 +1,304 @@
+//! Sets of channels, one bit per channel.
+
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, Schema, SchemaObject};
+use schemars::JsonSchema;
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::iter::FromIterator;
+use std::ops::{BitAnd, BitOr, BitOrAssign, BitXor, BitXorAssign};
+
+/// A set of channels, as held by the samples.
+///
+/// The first 64 channels are stored inline: captures that fit in them never allocate and are
+/// processed with plain integer operations. The channels of wider captures spill in `high`, which
+/// never ends with a zero word so that equal sets compare equal.
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct Bits {
+    low: u64,
+    high: Vec<u64>,
+}
+
+impl Bits {
+    /// The set holding only `channel`.
+    pub fn bit(channel: usize) -> Self {
+        let mut bits = Self::default();
+        bits.set(channel, true);
+        bits
+    }
+
+    /// Reads a little endian sample, as stored by the logic analyzers.
+    pub fn from_le_bytes(bytes: &[u8]) -> Self {
+        let mut words = bytes.chunks(8).map(|chunk| {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            u64::from_le_bytes(word)
+        });
+        let mut bits = Self {
+            low: words.next().unwrap_or(0),
+            high: words.collect(),
+        };
+        bits.normalize();
+        bits
+    }
+
+    fn normalize(&mut self) {
+        while self.high.last() == Some(&0) {
+            self.high.pop();
+        }
+    }
+
+    fn word(&self, index: usize) -> u64 {
+        match index {
+            0 => self.low,
+            _ => self.high.get(index - 1).copied().unwrap_or(0),
+        }
+    }
+
+    fn words(&self) -> usize {
+        1 + self.high.len()
+    }
+
+    /// Combines two sets word by word.
+    fn zip_with(&self, other: &Bits, f: impl Fn(u64, u64) -> u64) -> Bits {
+        if self.high.is_empty() && other.high.is_empty() {
+            return Bits::from(f(self.low, other.low));
+        }
+        let mut bits = Bits {
+            low: f(self.low, other.low),
+            high: (1..self.words().max(other.words()))
+                .map(|i| f(self.word(i), other.word(i)))
+                .collect(),
+        };
+        bits.normalize();
+        bits
+    }
+
+    /// The set as an integer, if it fits in the first 64 channels.
+    pub fn as_u64(&self) -> Option<u64> {
+        Some(self.low).filter(|_| self.high.is_empty())
+    }
+
+    pub fn get(&self, channel: usize) -> bool {
+        (self.word(channel / 64) >> (channel % 64)) & 1 != 0
+    }
+
+    pub fn set(&mut self, channel: usize, value: bool) {
+        let (index, bit) = (channel / 64, 1 << (channel % 64));
+        let word = match index {
+            0 => &mut self.low,
+            _ if value => {
+                if self.high.len() < index {
+                    self.high.resize(index, 0);
+                }
+                &mut self.high[index - 1]
+            }
+            _ => match self.high.get_mut(index - 1) {
+                Some(word) => word,
+                None => return,
+            },
+        };
+        if value {
+            *word |= bit;
+        } else {
+            *word &= !bit;
+            self.normalize();
+        }
+    }
+
+    /// Removes the channels of `other` from the set.
+    pub fn remove(&mut self, other: &Bits) {
+        *self = self.zip_with(other, |a, b| a & !b);
+    }
+
+    /// Removes the channels from `count` onward.
+    pub fn truncate(&mut self, count: usize) {
+        if count < 64 {
+            self.low &= !(!0 << count);
+            self.high.clear();
+            return;
+        }
+        // `high[index - 1]` holds the channels from `index * 64`, the count ending in it or before
+        let (index, shift) = (count / 64, count % 64);
+        if shift == 0 {
+            self.high.truncate(index - 1);
+        } else {
+            self.high.truncate(index);
+            if let Some(word) = self.high.get_mut(index - 1) {
+                *word &= !(!0 << shift);
+            }
+        }
+        self.normalize();
+    }
+
+    /// Moves all the channels up by `shift`.
+    pub fn shifted(&self, shift: usize) -> Bits {
+        if self.high.is_empty() && self.low.leading_zeros() as usize >= shift {
+            return Bits::from(self.low << shift);
+        }
+        let (offset, shift) = (shift / 64, shift % 64);
+        let mut words = vec![0; offset];
+        let mut carry = 0;
+        for i in 0..self.words() {
+            let word = self.word(i);
+            words.push((word << shift) | carry);
+            carry = if shift == 0 { 0 } else { word >> (64 - shift) };
+        }
+        words.push(carry);
+        let mut bits = Bits {
+            low: words[0],
+            high: words.split_off(1),
+        };
+        bits.normalize();
+        bits
+    }
+
+    /// Iterates over the channels in the set.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.words()).flat_map(move |i| {
+            let word = self.word(i);
+            (0..64)
+                .filter(move |bit| (word >> bit) & 1 != 0)
+                .map(move |bit| i * 64 + bit)
+        })
+    }
+}
+
+impl From<u64> for Bits {
+    fn from(low: u64) -> Self {
+        Self {
+            low,
+            high: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Debug for Bits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.high.is_empty() {
+            return fmt::Debug::fmt(&self.low, f);
+        }
+        write!(f, "0x")?;
+        for word in self.high.iter().rev() {
+            write!(f, "{:016x}_", word)?;
+        }
+        write!(f, "{:016x}", self.low)
+    }
+}
+
+/// A number, or its hex string when wider than 64 channels.
+impl Serialize for Bits {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.as_u64() {
+            Some(bits) => serializer.serialize_u64(bits),
+            None => serializer.serialize_str(&format!("{:?}", self)),
+        }
+    }
+}
+
+impl JsonSchema for Bits {
+    fn schema_name() -> String {
+        "Bits".to_owned()
+    }
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            instance_type: Some(vec![InstanceType::Integer, InstanceType::String].into()),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+impl BitAnd for &Bits {
+    type Output = Bits;
+    fn bitand(self, other: &Bits) -> Bits {
+        self.zip_with(other, |a, b| a & b)
+    }
+}
+
+impl BitOr for &Bits {
+    type Output = Bits;
+    fn bitor(self, other: &Bits) -> Bits {
+        self.zip_with(other, |a, b| a | b)
+    }
+}
+
+impl BitXor for &Bits {
+    type Output = Bits;
+    fn bitxor(self, other: &Bits) -> Bits {
+        self.zip_with(other, |a, b| a ^ b)
+    }
+}
+
+impl BitOrAssign<&Bits> for Bits {
+    fn bitor_assign(&mut self, other: &Bits) {
+        if other.high.is_empty() {
+            self.low |= other.low;
+        } else {
+            *self = &*self | other;
+        }
+    }
+}
+
+impl BitXorAssign<&Bits> for Bits {
+    fn bitxor_assign(&mut self, other: &Bits) {
+        if self.high.is_empty() && other.high.is_empty() {
+            self.low ^= other.low;
+        } else {
+            *self = &*self ^ other;
+        }
+    }
+}
+
+impl FromIterator<usize> for Bits {
+    fn from_iter<I: IntoIterator<Item = usize>>(channels: I) -> Self {
+        let mut bits = Bits::default();
+        for channel in channels {
+            bits.set(channel, true);
+        }
+        bits
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Bits;
+
+    #[test]
+    fn wide_sets_spill_and_normalize() {
+        let mut bits = Bits::bit(3);
+        assert_eq!(Bits::from(8), bits);
+        bits.set(130, true);
+        assert_eq!(vec![3, 130], bits.iter().collect::<Vec<_>>());
+        assert!(bits.get(130) && !bits.get(129) && !bits.get(1000));
+        assert_eq!(vec![67, 194], bits.shifted(64).iter().collect::<Vec<_>>());
+        assert_eq!(vec![4, 131], bits.shifted(1).iter().collect::<Vec<_>>());
+
+        bits.set(130, false);
+        assert_eq!(Bits::from(8), bits);
+        assert_eq!(
+            Bits::from(0b1000),
+            &vec![3, 70].into_iter().collect::<Bits>() ^ &Bits::bit(70)
+        );
+
+        let mut bits = Bits::from_le_bytes(&[0xff; 10]);
+        bits.truncate(66);
+        assert_eq!((0..66).collect::<Bits>(), bits);
+        bits.truncate(4);
+        assert_eq!(Bits::from(0xf), bits);
+    }
+
+    #[test]
+    fn truncates_on_word_boundaries() {
+        let bits = vec![3, 64, 100, 130].into_iter().collect::<Bits>();
+        let truncated = |count| {
+            let mut bits = bits.clone();
+            bits.truncate(count);
+            bits.iter().collect::<Vec<_>>()
+        };
+        assert_eq!(vec![3], truncated(64));
+        assert_eq!(vec![3, 64], truncated(65));
+        assert_eq!(vec![3, 64, 100], truncated(128));
+        assert_eq!(vec![3, 64, 100, 130], truncated(192));
+    }
+}
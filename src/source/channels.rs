@@ -0,0 +1,135 @@
+//! Names given to the channels of the samples.
+//!
+//! Sources register the names they know of (signal names, session metadata) as well as those given
+//! with `--name` or `--names`, so that decoders accept them in place of bit indices.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use anyhow::Context;
+use clap::{Arg, ArgMatches};
+
+struct Registry {
+    /// Channel of each name, along with the source that registered it.
+    names: BTreeMap<String, (usize, usize)>,
+    source: usize,
+}
+
+static NAMES: Mutex<Registry> = Mutex::new(Registry {
+    names: BTreeMap::new(),
+    source: 0,
+});
+
+/// Starts the registration of the names of another source.
+pub fn new_source() {
+    NAMES.lock().unwrap().source += 1;
+}
+
+/// Moves the channels named by the last source, when its samples are combined with another's.
+pub fn shift_last_source(shift: usize) {
+    let mut registry = NAMES.lock().unwrap();
+    let source = registry.source;
+    registry
+        .names
+        .values_mut()
+        .filter(|(_, s)| *s == source)
+        .for_each(|(channel, _)| *channel += shift);
+}
+
+pub fn register(name: &str, channel: usize) {
+    let mut registry = NAMES.lock().unwrap();
+    let source = registry.source;
+    registry.names.insert(name.to_owned(), (channel, source));
+}
+
+/// Resolves a channel given either as a bit index or as a registered name.
+pub fn resolve(channel: &str) -> Option<usize> {
+    channel.parse().ok().or_else(|| {
+        let registry = NAMES.lock().unwrap();
+        registry.names.get(channel).map(|(channel, _)| *channel)
+    })
+}
+
+/// Fetches a channel option, exiting with a clap error if it is not a valid channel.
+pub fn value_of(matches: &ArgMatches<'_>, arg: &str) -> Option<usize> {
+    matches.value_of(arg).map(|v| {
+        resolve(v).unwrap_or_else(|| {
+            clap::Error::value_validation_auto(format!(
+                "the argument '{}' isn't a valid channel: {:?}",
+                arg, v
+            ))
+            .exit()
+        })
+    })
+}
+
+/// Fetches a multiple channels option, exiting with a clap error if one is not a valid channel.
+pub fn values_of(matches: &ArgMatches<'_>, arg: &str) -> Option<Vec<usize>> {
+    matches.values_of(arg).map(|values| {
+        values
+            .map(|v| {
+                resolve(v).unwrap_or_else(|| {
+                    clap::Error::value_validation_auto(format!(
+                        "the argument '{}' isn't a valid channel: {:?}",
+                        arg, v
+                    ))
+                    .exit()
+                })
+            })
+            .collect()
+    })
+}
+
+/// Options naming channels, shared by the sources.
+pub fn args() -> [Arg<'static, 'static>; 2] {
+    [
+        Arg::from_usage(
+            "-n, --name [name=channel]... 'Names a channel (or aliases a name) so that the decoders accept it in place of its index.'",
+        )
+        .number_of_values(1),
+        Arg::from_usage("--names [file] 'Reads channel names from a file, one name=channel per line.'"),
+    ]
+}
+
+fn parse_name(definition: &str) -> anyhow::Result<(&str, usize)> {
+    let (name, channel) = definition
+        .split_once('=')
+        .with_context(|| format!("Invalid name {:?}, expected <name>=<channel>", definition))?;
+    let channel =
+        resolve(channel.trim()).with_context(|| format!("Unknown channel in {:?}", definition))?;
+    Ok((name.trim(), channel))
+}
+
+/// Registers the names given on the command line, after those found by the source so that they
+/// can alias them.
+pub fn register_from(matches: &ArgMatches<'_>) -> anyhow::Result<()> {
+    if let Some(path) = matches.value_of("names") {
+        let content =
+            std::fs::read_to_string(path).with_context(|| format!("Reading {:?}", path))?;
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, channel) = parse_name(line)?;
+            register(name, channel);
+        }
+    }
+    for definition in matches.values_of("name").into_iter().flatten() {
+        let (name, channel) = parse_name(definition)?;
+        register(name, channel);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn names_can_alias_names() {
+        super::register("test.D0", 3);
+        assert_eq!(Some(3), super::resolve("test.D0"));
+        assert_eq!(Some(7), super::resolve("7"));
+        assert_eq!(("TXD", 3), super::parse_name("TXD = test.D0").unwrap());
+        assert!(super::parse_name("TXD=unknown").is_err());
+        assert!(super::parse_name("TXD").is_err());
+    }
+}
@@ -0,0 +1,216 @@
+//! Digital CSV exports of Saleae Logic 1.x and Logic 2.
+//!
+//! The first column holds the time in seconds (or a sample index, scaled by `--freq`), the
+//! following ones the level of each channel. Exports may contain every sample, unchanged rows
+//! are collapsed.
+
+use std::io::BufRead;
+
+use anyhow::{anyhow, Context, Result};
+use clap::{value_t, Arg};
+
+use super::{channels, open_input, origin, Bits, Input, Sample};
+use crate::pipeline::{self, Event, EventIterator, Timestamp};
+
+/// Parses the header row into the bit receiving each column.
+fn parse_header(header: &str) -> Result<(bool, Vec<usize>)> {
+    let mut columns = header.split(',').map(|c| c.trim().trim_matches('"'));
+    let is_time = columns
+        .next()
+        .is_some_and(|c| c.to_lowercase().starts_with("time"));
+
+    let columns = columns.collect::<Vec<_>>();
+    anyhow::ensure!(!columns.is_empty(), "No channel in the header");
+
+    let bits = columns
+        .iter()
+        .enumerate()
+        .map(
+            |(i, name)| match name.strip_prefix("Channel ").map(str::parse::<usize>) {
+                Some(Ok(bit)) => bit,
+                _ => {
+                    let bit = i;
+                    if !name.is_empty() {
+                        channels::register(name, bit);
+                    }
+                    bit
+                }
+            },
+        )
+        .collect();
+    Ok((is_time, bits))
+}
+
+/// `freq` is the sample rate of an index column, `None` if it holds times.
+fn parse_row(row: &str, bits: &[usize], freq: Option<f64>) -> Result<(Timestamp, Bits)> {
+    let mut values = row.split(',').map(str::trim);
+    let time = values.next().unwrap();
+    let ts = match freq {
+        Some(freq) => time
+            .parse::<i64>()
+            .map(|index| Timestamp::from_sample(index, freq))
+            .context("Invalid sample index")?,
+        None => time.parse().context("Invalid time")?,
+    };
+
+    let mut levels = Bits::default();
+    let mut count = 0;
+    for (value, bit) in values.zip(bits) {
+        match value {
+            "0" => {}
+            "1" => levels.set(*bit, true),
+            _ => return Err(anyhow!("Invalid level {:?}", value)),
+        }
+        count += 1;
+    }
+    anyhow::ensure!(
+        count == bits.len(),
+        "Expected {} channels, found {}",
+        bits.len(),
+        count
+    );
+    Ok((ts, levels))
+}
+
+pub struct CsvParser<T> {
+    lines: std::iter::Enumerate<std::io::Lines<T>>,
+    bits: Vec<usize>,
+    /// Sample rate of the first column, `None` if it holds times.
+    freq: Option<f64>,
+
+    last: Option<Bits>,
+    current_ts: Timestamp,
+}
+
+impl<T: BufRead> CsvParser<T> {
+    pub fn new(input: T, freq: f64) -> Result<Self> {
+        let mut lines = input.lines().enumerate();
+        let header = match lines.next() {
+            Some((_, line)) => line?,
+            None => anyhow::bail!("Empty file"),
+        };
+        let (is_time, bits) = parse_header(&header)?;
+
+        Ok(Self {
+            lines,
+            bits,
+            freq: Some(freq).filter(|_| !is_time),
+            last: None,
+            current_ts: Timestamp::ZERO,
+        })
+    }
+}
+
+impl<T: BufRead> Iterator for CsvParser<T> {
+    type Item = Event;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (n, line) = self.lines.next()?;
+            let row = match line {
+                Ok(line) if line.trim().is_empty() => continue,
+                Ok(line) => parse_row(&line, &self.bits, self.freq),
+                Err(e) => Err(e.into()),
+            };
+            match row {
+                Ok((ts, levels)) => {
+                    self.current_ts = ts;
+                    if self.last.as_ref() != Some(&levels) {
+                        self.last = Some(levels.clone());
+                        return Some((ts, Ok(Box::new(Sample::from(levels)))));
+                    }
+                }
+                Err(e) => {
+                    return Some((self.current_ts, Err(e.context(format!("line {}", n + 1)))))
+                }
+            }
+        }
+    }
+}
+
+impl<T: BufRead + 'static> EventIterator for CsvParser<T> {
+    fn into_iterator(self: Box<Self>) -> Box<dyn Iterator<Item = Event>> {
+        self
+    }
+    fn event_type(&self) -> std::any::TypeId {
+        std::any::TypeId::of::<super::Sample>()
+    }
+    fn event_type_name(&self) -> &'static str {
+        std::any::type_name::<super::Sample>()
+    }
+}
+
+pub fn build(pipeline: &mut Vec<Box<dyn EventIterator>>, args: &[String]) {
+    build_from(pipeline, args, None)
+}
+
+/// Same as [`build`], reading from an already opened input.
+pub fn build_from(
+    pipeline: &mut Vec<Box<dyn EventIterator>>,
+    args: &[String],
+    input: Option<Input>,
+) {
+    let args = clap::SubCommand::with_name("csv")
+        .setting(clap::AppSettings::NoBinaryName)
+        .args(&[
+            Arg::from_usage(
+                "-f, --freq [freq] 'Sample frequency, used when the first column is a sample index.'",
+            )
+            .default_value("1."),
+            Arg::with_name("file").help("Input CSV export, stdin if absent or '-'."),
+        ])
+        .args(&channels::args())
+        .args(&origin::args())
+        .get_matches_from(args);
+
+    let freq = value_t!(args, "freq", f64).unwrap_or_else(|e| e.exit());
+    let input = input
+        .map_or_else(|| open_input(args.value_of("file")), Ok)
+        .context("Opening the capture")
+        .unwrap_or_else(|e| pipeline::fail(&format!("{:#}", e)));
+    let parser = CsvParser::new(input, freq)
+        .context("Parsing CSV header.")
+        .unwrap();
+    channels::register_from(&args).unwrap();
+    pipeline.push(origin::apply(
+        Box::new(parser),
+        &args,
+        origin::Capture {
+            trigger: None,
+            initial_state: true,
+        },
+    ));
+}
+
+#[cfg(test)]
+mod test {
+    use crate::pipeline;
+    use crate::source::Sample;
+
+    #[test]
+    fn collapses_unchanged_rows() {
+        let input = "Time [s],Channel 0,Channel 2\n\
+                     0.000000000,1,0\n\
+                     0.000000010,1,0\n\
+                     0.000000020,0,1\n\
+                     0.000000030,x,1\n\
+                     0.000000040,0\n\
+                     0.000000050,1,1\n";
+        let parser = super::CsvParser::new(input.as_bytes(), 1.).unwrap();
+        let events = parser
+            .map(|(ts, ev)| ev.map(|ev| (ts.as_secs_f64(), *pipeline::downcast::<Sample>(ev))))
+            .collect::<Vec<_>>();
+
+        assert_eq!(5, events.len());
+        assert_eq!((0., Sample::from(0b001)), *events[0].as_ref().unwrap());
+        assert_eq!(
+            (0.000_000_02, Sample::from(0b100)),
+            *events[1].as_ref().unwrap()
+        );
+        assert!(events[2].is_err());
+        assert!(events[3].is_err());
+        assert_eq!(
+            (0.000_000_05, Sample::from(0b101)),
+            *events[4].as_ref().unwrap()
+        );
+    }
+}
@@ -1,19 +1,27 @@
 use std::{convert::TryInto, io::Read};
 
 use anyhow::Context;
-use clap::{value_t, ArgMatches};
+use clap::{value_t, Arg, ArgMatches};
 
-use super::Sample;
-use crate::pipeline::{Event, EventIterator};
+use super::{channels, open_input, origin, Input, Sample};
+use crate::pipeline::{self, Event, EventIterator, Timestamp};
 
+/// Reads the binary exports of Saleae Logic 1.x.
+///
+/// Records are made of an optional 8-byte sample index followed by a 1, 2, 4 or 8-byte sample.
+/// "Export every sample" files have no index, the position of the record is used instead.
 pub struct LogicDataParser<T>
 where
     T: Read,
 {
     input: T,
     freq: f64,
+    width: usize,
+    every_sample: bool,
 
-    current_ts: f64,
+    index: i64,
+    last: Option<u64>,
+    current_ts: Timestamp,
     stopped: bool,
 }
 
@@ -29,10 +37,29 @@ where
         Self {
             input,
             freq,
-            current_ts: 0.,
+            width: value_t!(matches, "width", usize).unwrap_or_else(|e| e.exit()),
+            every_sample: matches.is_present("every-sample"),
+            index: 0,
+            last: None,
+            current_ts: Timestamp::ZERO,
             stopped: false,
         }
     }
+
+    /// Reads a whole record, `None` meaning the input ended on a record boundary.
+    fn read_record(&mut self, buffer: &mut [u8]) -> anyhow::Result<Option<()>> {
+        let mut len = 0;
+        while len < buffer.len() {
+            match self.input.read(&mut buffer[len..]) {
+                Ok(0) if len == 0 => return Ok(None),
+                Ok(0) => anyhow::bail!("Truncated record"),
+                Ok(n) => len += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(Some(()))
+    }
 }
 
 impl<T> Iterator for LogicDataParser<T>
@@ -41,28 +68,48 @@ where
 {
     type Item = Event;
     fn next(&mut self) -> Option<Self::Item> {
-        if self.stopped {
-            return None;
-        }
+        let ts_len = if self.every_sample { 0 } else { 8 };
+        let mut buffer = [0; 16];
 
-        let mut buffer = [0; 8];
+        loop {
+            if self.stopped {
+                return None;
+            }
 
-        let ts = match self.input.read_exact(&mut buffer) {
-            Ok(_) => {
-                let ts =
-                    i64::from_le_bytes(buffer[..8].try_into().unwrap_or_else(|_| unreachable!()));
-                let ts = ts as f64; // lossy conversion from i64 to f64;
-                ts / self.freq
+            let record = &mut buffer[..ts_len + self.width];
+            match self.read_record(record) {
+                Ok(Some(())) => {}
+                Ok(None) => {
+                    self.stopped = true;
+                    return None;
+                }
+                Err(e) => {
+                    self.stopped = true;
+                    return Some((self.current_ts, Err(e)));
+                }
             }
-            Err(e) => return Some((self.current_ts, Err(e.into()))),
-        };
-        let smp = match self.input.read_exact(&mut buffer[..1]) {
-            Ok(_) => Sample(buffer[0].into()),
-            Err(e) => return Some((self.current_ts, Err(e.into()))),
-        };
-
-        self.current_ts = ts;
-        Some((ts, Ok(Box::new(smp))))
+
+            let index = if self.every_sample {
+                self.index
+            } else {
+                i64::from_le_bytes(buffer[..8].try_into().unwrap_or_else(|_| unreachable!()))
+            };
+            self.index += 1;
+
+            let mut levels = [0; 8];
+            levels[..self.width].copy_from_slice(&buffer[ts_len..ts_len + self.width]);
+            let levels = u64::from_le_bytes(levels);
+
+            // unchanged samples carry no information for the decoders
+            if self.last == Some(levels) {
+                continue;
+            }
+            self.last = Some(levels);
+
+            let ts = Timestamp::from_sample(index, self.freq);
+            self.current_ts = ts;
+            return Some((ts, Ok(Box::new(Sample::from(levels)))));
+        }
     }
 }
 
@@ -78,26 +125,99 @@ impl<T: Read + 'static> EventIterator for LogicDataParser<T> {
     }
 }
 
-pub fn build(pipeline: &mut Vec<Box<dyn EventIterator>>, args: &Vec<String>) {
-    use clap::Arg;
-    let args = clap::SubCommand::with_name("logic2")
+/// Options of the source, besides its input.
+pub fn options() -> [Arg<'static, 'static>; 3] {
+    [
+        Arg::from_usage("-f, --freq [freq] 'Sample frequency (only used on binary input)'")
+            .default_value("1."),
+        Arg::from_usage("-w, --width [bytes] 'Size of a sample in bytes.'")
+            .possible_values(&["1", "2", "4", "8"])
+            .default_value("1"),
+        Arg::from_usage(
+            "--every-sample 'Records have no timestamp, they are spaced by 1/freq seconds.'",
+        ),
+    ]
+}
+
+pub fn build(pipeline: &mut Vec<Box<dyn EventIterator>>, args: &[String]) {
+    build_from(pipeline, args, None)
+}
+
+/// Same as [`build`], reading from an already opened input.
+pub fn build_from(
+    pipeline: &mut Vec<Box<dyn EventIterator>>,
+    args: &[String],
+    input: Option<Input>,
+) {
+    let args = clap::SubCommand::with_name("logic")
         .setting(clap::AppSettings::NoBinaryName)
-        .args(&[
-            Arg::from_usage("-f, --freq [freq] 'Sample frequency (only used on binary input)'")
-                .default_value("1."),
-            Arg::with_name("file")
-                .help("Input file. (may be a folder in case of Saleae Logic 2 exports.)")
-                .required(true),
-        ])
+        .args(&options())
+        .arg(Arg::with_name("file").help("Input file, stdin if absent or '-'."))
+        .args(&channels::args())
+        .args(&origin::args())
         .get_matches_from(args);
 
-    let file = std::fs::File::open(
-        args.value_of("file")
-            .context("Fetching file argument")
-            .unwrap(),
-    )
-    .context("Openning capture file.")
-    .unwrap();
-    let parser = Box::new(LogicDataParser::new(file, &args));
-    pipeline.push(parser);
+    let input = input
+        .map_or_else(|| open_input(args.value_of("file")), Ok)
+        .context("Opening the capture")
+        .unwrap_or_else(|e| pipeline::fail(&format!("{:#}", e)));
+    let parser = Box::new(LogicDataParser::new(input, &args));
+    channels::register_from(&args).unwrap();
+    pipeline.push(origin::apply(
+        parser,
+        &args,
+        origin::Capture {
+            trigger: None,
+            initial_state: true,
+        },
+    ));
+}
+
+#[cfg(test)]
+mod test {
+    use crate::pipeline;
+    use crate::source::Sample;
+
+    fn parse(args: &[&str], input: Vec<u8>) -> Vec<(f64, u64)> {
+        let matches = clap::SubCommand::with_name("logic")
+            .setting(clap::AppSettings::NoBinaryName)
+            .args(&[
+                clap::Arg::from_usage("-f, --freq [freq]").default_value("1."),
+                clap::Arg::from_usage("-w, --width [bytes]").default_value("1"),
+                clap::Arg::from_usage("--every-sample"),
+            ])
+            .get_matches_from(args);
+        super::LogicDataParser::new(std::io::Cursor::new(input), &matches)
+            .map(|(ts, ev)| {
+                (
+                    ts.as_secs_f64(),
+                    pipeline::downcast::<Sample>(ev.unwrap())
+                        .levels
+                        .as_u64()
+                        .unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reads_wide_samples_on_change() {
+        let mut input = Vec::new();
+        for (index, sample) in [(0i64, 0x0102u16), (4, 0x8001), (6, 0x8001)] {
+            input.extend(&index.to_le_bytes());
+            input.extend(&sample.to_le_bytes());
+        }
+        assert_eq!(
+            vec![(0., 0x0102), (2., 0x8001)],
+            parse(&["-w", "2", "-f", "2"], input)
+        );
+    }
+
+    #[test]
+    fn collapses_every_sample_records() {
+        assert_eq!(
+            vec![(0., 1), (0.5, 0), (1.5, 1)],
+            parse(&["--every-sample", "-f", "2"], vec![1, 0, 0, 1, 1])
+        );
+    }
 }
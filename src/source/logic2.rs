@@ -1,24 +1,237 @@
+use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
-use std::io::Read;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
 
-use anyhow::{anyhow, Result};
-use clap::Arg;
+use anyhow::{anyhow, Context, Result};
+use clap::{value_t, Arg};
 use indicatif::{ProgressBar, ProgressStyle};
 use itertools::Itertools;
+use serde_json::Value;
 
-use super::Sample;
-use crate::pipeline::{Event, EventIterator};
+use super::archive::{Archive, ZIP_MAGIC};
+use super::{channels, open_input, origin, peek, Bits, Input, Sample};
+use crate::pipeline::{self, Event, EventIterator, Timestamp};
 
-#[derive(Debug)]
-struct Channel {
+/// Progress is reported by steps of this size.
+const PROGRESS_STEP: u64 = 64 * 1024;
+
+/// Reports the bytes read from a channel file on the progress bar.
+struct Progress<R> {
+    inner: R,
+    progress_bar: ProgressBar,
+    unreported: u64,
+}
+
+impl<R: Read> Read for Progress<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.unreported += len as u64;
+        if len == 0 || self.unreported >= PROGRESS_STEP {
+            self.progress_bar.inc(std::mem::take(&mut self.unreported));
+        }
+        Ok(len)
+    }
+}
+
+type ChannelInput = BufReader<Progress<Box<dyn Read>>>;
+
+/// Reads up to `buf.len()` bytes, stopping early only at the end of the input.
+fn read_header(input: &mut impl Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match input.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len)
+}
+
+fn read_array<const N: usize>(input: &mut impl Read) -> std::io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    input.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Lazily reads the transitions of a digital channel file.
+///
+/// Version 1 files are split in chunks, each starting with the state of the channel.
+struct DigitalChannel {
+    id: u32,
+    input: ChannelInput,
+    /// Chunks left after the current one.
+    chunks: u64,
+    remaining: u64,
+    state: bool,
+}
+
+impl DigitalChannel {
+    fn next_transition(&mut self) -> Result<Option<Timestamp>> {
+        loop {
+            if self.remaining != 0 {
+                self.remaining -= 1;
+                self.state = !self.state;
+                let ts = f64::from_le_bytes(read_array(&mut self.input)?);
+                return Ok(Some(Timestamp::from_secs_f64(ts)));
+            }
+            if self.chunks == 0 {
+                return Ok(None);
+            }
+            self.chunks -= 1;
+
+            let (initial_state, _, begin_time, _, num_transitions) =
+                parse_digital_chunk_header(&read_array::<36>(&mut self.input)?)?;
+            self.remaining = num_transitions;
+            if (initial_state == 1) != self.state {
+                self.state = initial_state == 1;
+                return Ok(Some(Timestamp::from_secs_f64(begin_time)));
+            }
+        }
+    }
+}
+
+impl Iterator for DigitalChannel {
+    type Item = Result<(u32, Timestamp)>;
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_transition() {
+            Ok(ts) => ts.map(|ts| Ok((self.id, ts))),
+            Err(e) => {
+                self.remaining = 0;
+                self.chunks = 0;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Converts an analog level to a logic level.
+#[derive(Debug, Clone, Copy)]
+pub struct Threshold {
+    pub level: f32,
+    pub hysteresis: f32,
+}
+
+impl Threshold {
+    fn update(&self, state: bool, value: f32) -> bool {
+        if state {
+            value >= self.level - self.hysteresis / 2.
+        } else {
+            value > self.level + self.hysteresis / 2.
+        }
+    }
+}
+
+/// Settings used to turn analog channels into bits of the samples.
+#[derive(Debug, Clone)]
+pub struct AnalogOptions {
+    pub thresholds: HashMap<u32, Threshold>,
+    pub default: Threshold,
+    /// Bit of the sample receiving analog channel 0.
+    pub base: u32,
+}
+
+impl AnalogOptions {
+    fn threshold(&self, channel: u32) -> Threshold {
+        self.thresholds
+            .get(&channel)
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+/// Lazily thresholds the samples of an analog channel file into transitions.
+///
+/// Version 1 files are split in waveforms, each with its own timing.
+struct AnalogChannel {
     id: u32,
-    initial_state: bool,
+    input: ChannelInput,
+    /// Waveforms left after the current one.
+    waveforms: u64,
+    remaining: u64,
+
+    begin_time: Timestamp,
+    /// Trigger marker of the waveforms.
+    trigger_time: Option<Timestamp>,
+    /// Sample rate after downsampling.
+    sample_rate: f64,
+    index: i64,
+    threshold: Threshold,
+    state: bool,
+}
+
+impl AnalogChannel {
+    fn read_sample(&mut self) -> Option<Result<f32>> {
+        while self.remaining == 0 {
+            if self.waveforms == 0 {
+                return None;
+            }
+            self.waveforms -= 1;
+
+            let header = read_array::<40>(&mut self.input)
+                .map_err(anyhow::Error::from)
+                .and_then(|buf| parse_analog_waveform_header(&buf));
+            match header {
+                Ok((begin_time, trigger_time, sample_rate, downsample, num_samples)) => {
+                    if sample_rate <= 0. {
+                        self.waveforms = 0;
+                        return Some(Err(anyhow!("Invalid sample rate {}", sample_rate)));
+                    }
+                    self.begin_time = Timestamp::from_secs_f64(begin_time);
+                    self.trigger_time = Some(Timestamp::from_secs_f64(trigger_time));
+                    self.sample_rate = sample_rate / (downsample.max(1) as f64);
+                    self.index = 0;
+                    self.remaining = num_samples;
+                }
+                Err(e) => {
+                    self.waveforms = 0;
+                    return Some(Err(e));
+                }
+            }
+        }
+
+        match read_array(&mut self.input) {
+            Ok(buf) => {
+                self.remaining -= 1;
+                self.index += 1;
+                Some(Ok(f32::from_le_bytes(buf)))
+            }
+            Err(e) => {
+                self.remaining = 0;
+                self.waveforms = 0;
+                Some(Err(e.into()))
+            }
+        }
+    }
+}
 
-    transitions: Vec<f64>,
+impl Iterator for AnalogChannel {
+    type Item = Result<(u32, Timestamp)>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let value = match self.read_sample()? {
+                Ok(value) => value,
+                Err(e) => return Some(Err(e)),
+            };
+            let state = self.threshold.update(self.state, value);
+            if state != self.state {
+                self.state = state;
+                let ts = self.begin_time + Timestamp::from_sample(self.index - 1, self.sample_rate);
+                return Some(Ok((self.id, ts)));
+            }
+        }
+    }
 }
 
 pub struct LogicData<T> {
     transitions: T,
+    current_ts: Timestamp,
+    /// Time of the trigger marker, if the capture has one.
+    pub trigger: Option<Timestamp>,
+    progress_bar: ProgressBar,
 }
 
 fn parse_common_header(buf: &[u8]) -> anyhow::Result<(u32, u32)> {
@@ -40,135 +253,422 @@ fn parse_digital_header(buf: &[u8]) -> anyhow::Result<(u32, f64, f64, u64)> {
     Ok((initial_state, begin_time, end_time, num_transitions))
 }
 
-pub fn new_parser(path: &str) -> Result<LogicData<impl Iterator<Item = (f64, u64)>>> {
-    // display something while processing
-    let progress_bar = ProgressBar::new(!0);
-    progress_bar.set_style(
-        ProgressStyle::default_spinner()
-            .tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏ ")
-            .template(" {spinner} {msg}"),
-    );
-    progress_bar.set_message("Processing transitions");
-    progress_bar.enable_steady_tick(80);
+fn parse_analog_header(buf: &[u8]) -> anyhow::Result<(f64, u64, u64, u64)> {
+    anyhow::ensure!(buf.len() == 32, "Incomplete file header");
 
-    // select valid files
-    let channels = std::fs::read_dir(path)?
-        .map(|entry| -> anyhow::Result<_> {
-            let entry = entry?;
+    let begin_time = buf[..8].try_into().map(f64::from_le_bytes).unwrap();
+    let sample_rate = buf[8..16].try_into().map(u64::from_le_bytes).unwrap();
+    let downsample = buf[16..24].try_into().map(u64::from_le_bytes).unwrap();
+    let num_samples = buf[24..].try_into().map(u64::from_le_bytes).unwrap();
+    Ok((begin_time, sample_rate, downsample, num_samples))
+}
 
-            // ignore non-files entries
-            if !std::fs::metadata(entry.path())?.is_file() {
-                return Ok(None);
+fn parse_digital_chunk_header(buf: &[u8]) -> anyhow::Result<(u32, f64, f64, f64, u64)> {
+    anyhow::ensure!(buf.len() == 36, "Incomplete chunk header");
+
+    let initial_state = buf[..4].try_into().map(u32::from_le_bytes).unwrap();
+    let sample_rate = buf[4..12].try_into().map(f64::from_le_bytes).unwrap();
+    let begin_time = buf[12..20].try_into().map(f64::from_le_bytes).unwrap();
+    let end_time = buf[20..28].try_into().map(f64::from_le_bytes).unwrap();
+    let num_transitions = buf[28..].try_into().map(u64::from_le_bytes).unwrap();
+    Ok((
+        initial_state,
+        sample_rate,
+        begin_time,
+        end_time,
+        num_transitions,
+    ))
+}
+
+fn parse_analog_waveform_header(buf: &[u8]) -> anyhow::Result<(f64, f64, f64, u64, u64)> {
+    anyhow::ensure!(buf.len() == 40, "Incomplete waveform header");
+
+    let begin_time = buf[..8].try_into().map(f64::from_le_bytes).unwrap();
+    let trigger_time = buf[8..16].try_into().map(f64::from_le_bytes).unwrap();
+    let sample_rate = buf[16..24].try_into().map(f64::from_le_bytes).unwrap();
+    let downsample = buf[24..32].try_into().map(u64::from_le_bytes).unwrap();
+    let num_samples = buf[32..].try_into().map(u64::from_le_bytes).unwrap();
+    Ok((
+        begin_time,
+        trigger_time,
+        sample_rate,
+        downsample,
+        num_samples,
+    ))
+}
+
+type Channel = Box<dyn Iterator<Item = Result<(u32, Timestamp)>>>;
+
+/// Extracts the kind and index of a channel from its file name.
+///
+/// Exports name their files `digital_N.bin` while sessions use `digital-N.bin`.
+fn parse_file_name(file_name: &str) -> Option<(bool, u32)> {
+    let (is_analog, rest) = match file_name.strip_prefix("digital") {
+        Some(rest) => (false, rest),
+        None => (true, file_name.strip_prefix("analog")?),
+    };
+    let id = rest
+        .strip_prefix(|c| c == '_' || c == '-')?
+        .strip_suffix(".bin")?
+        .parse()
+        .ok()?;
+    Some((is_analog, id))
+}
+
+/// Opens a channel file and returns its id, initial state, trigger marker and transitions.
+///
+/// The length of streams is unknown, and their name may not give the channel: the file type then
+/// tells digital from analog and channel 0 is used.
+fn open_channel(
+    input: Box<dyn Read>,
+    file_len: Option<u64>,
+    file_name: &str,
+    analog: &AnalogOptions,
+    progress_bar: &ProgressBar,
+) -> Result<(u32, bool, Option<Timestamp>, Channel)> {
+    let (kind, chan_id) = match parse_file_name(file_name) {
+        Some((is_analog, chan_id)) => (Some(is_analog), chan_id),
+        None if file_len.is_none() => (None, 0),
+        None => anyhow::bail!("Invalid filename format {:?}", file_name),
+    };
+
+    let mut file = BufReader::new(Progress {
+        inner: input,
+        progress_bar: progress_bar.clone(),
+        unreported: 0,
+    });
+    let mut buf = [0; 32];
+    let len = read_header(&mut file, &mut buf[..16])?;
+    let (version, is_analog) = match (parse_common_header(&buf[..len])?, kind) {
+        ((v @ 0..=1, 0), Some(false) | None) => (v, false),
+        ((v @ 0..=1, 1), Some(true) | None) => (v, true),
+        ((0..=1, d), _) => return Err(anyhow!("Unexpected file type {}.", d)),
+        ((v, _), _) => return Err(anyhow!("Unsupported file format version {}.", v)),
+    };
+    let is_corrupted = |len| file_len.is_some_and(|file_len| file_len != len);
+
+    match (is_analog, version) {
+        (false, 0) => {
+            let len = read_header(&mut file, &mut buf[..28])?;
+            let (initial_state, _, _, num_transitions) = parse_digital_header(&buf[..len])?;
+            if is_corrupted(16 + 28 + num_transitions * 8) {
+                anyhow::bail!("Corrupted file {:?}", file_name);
             }
 
-            let file_name = if let Some(file_name) = entry.file_name().to_str() {
-                file_name.to_owned()
-            } else {
-                return Ok(None);
+            let channel = DigitalChannel {
+                id: chan_id,
+                input: file,
+                chunks: 0,
+                remaining: num_transitions,
+                state: initial_state == 1,
+            };
+            Ok((chan_id, channel.state, None, Box::new(channel)))
+        }
+        (false, _) => {
+            let chunk_count = u64::from_le_bytes(read_array(&mut file)?);
+            let mut channel = DigitalChannel {
+                id: chan_id,
+                input: file,
+                chunks: chunk_count,
+                remaining: 0,
+                state: false,
+            };
+            // the first chunk gives the initial state
+            if chunk_count != 0 {
+                let (initial_state, _, _, _, num_transitions) =
+                    parse_digital_chunk_header(&read_array::<36>(&mut channel.input)?)?;
+                channel.chunks -= 1;
+                channel.remaining = num_transitions;
+                channel.state = initial_state == 1;
+            }
+            Ok((chan_id, channel.state, None, Box::new(channel)))
+        }
+        (true, version) => {
+            let id = chan_id + analog.base;
+            let threshold = analog.threshold(chan_id);
+            let mut channel = AnalogChannel {
+                id,
+                input: file,
+                waveforms: 0,
+                remaining: 0,
+                begin_time: Timestamp::ZERO,
+                trigger_time: None,
+                sample_rate: 1.,
+                index: 0,
+                threshold,
+                state: false,
             };
+            if version == 0 {
+                let len = read_header(&mut channel.input, &mut buf[..32])?;
+                let (begin_time, sample_rate, downsample, num_samples) =
+                    parse_analog_header(&buf[..len])?;
+                if is_corrupted(16 + 32 + num_samples * 4) {
+                    anyhow::bail!("Corrupted file {:?}", file_name);
+                }
+                anyhow::ensure!(sample_rate != 0, "Invalid sample rate in {:?}", file_name);
+                channel.remaining = num_samples;
+                channel.begin_time = Timestamp::from_secs_f64(begin_time);
+                channel.sample_rate = (sample_rate as f64) / (downsample.max(1) as f64);
+            } else {
+                channel.waveforms = u64::from_le_bytes(read_array(&mut channel.input)?);
+            }
 
-            let chan_id = file_name
-                .strip_prefix("digital_")
-                .and_then(|s| s.strip_suffix(".bin"))
-                .and_then(|s| s.parse().ok())
-                .ok_or_else(|| anyhow!("Invalid filename format {:?}", file_name))?;
-
-            let mut file = std::fs::File::open(entry.path())?;
-            let mut buf = [0; 32];
-            let initial_state = {
-                let len = file.read(&mut buf[..16])?;
-                match parse_common_header(&buf[..len])? {
-                    (0, 0) => {}
-                    (0, d) => return Err(anyhow!("Unexpected file type {}.", d)),
-                    (v, _) => return Err(anyhow!("Unsupported file format version {}.", v)),
+            // the first sample gives the initial state
+            if let Some(value) = channel.read_sample().transpose()? {
+                channel.state = value > threshold.level;
+            }
+            Ok((id, channel.state, channel.trigger_time, Box::new(channel)))
+        }
+    }
+}
+
+/// Collects the names and indices of the channels described in a session's metadata.
+///
+/// The layout of `meta.json` changes across Logic 2 releases, so any object holding both a
+/// name and a channel index is taken. Analog channels are told apart by their type or by
+/// the key they are stored under.
+fn session_channels(meta: &Value) -> Vec<(String, bool, u32)> {
+    fn walk(value: &Value, analog: bool, found: &mut Vec<(String, bool, u32)>) {
+        match value {
+            Value::Array(items) => items.iter().for_each(|item| walk(item, analog, found)),
+            Value::Object(map) => {
+                let channel = map.get("channel").filter(|c| c.is_object());
+                let field = |key: &str| map.get(key).or_else(|| channel?.get(key));
+                let analog = field("type")
+                    .and_then(Value::as_str)
+                    .map_or(analog, |t| t.to_lowercase().contains("analog"));
+                let name = ["name", "label", "alias"]
+                    .iter()
+                    .find_map(|key| map.get(*key)?.as_str())
+                    .filter(|name| !name.is_empty());
+                if let (Some(name), Some(index)) = (name, field("index").and_then(Value::as_u64)) {
+                    found.push((name.to_owned(), analog, index as u32));
                 }
 
-                let len = file.read(&mut buf[..28])?;
-                parse_digital_header(&buf[..len])?.0
-            };
+                for (key, value) in map {
+                    let key = key.to_lowercase();
+                    let analog = if key.contains("analog") {
+                        true
+                    } else if key.contains("digital") {
+                        false
+                    } else {
+                        analog
+                    };
+                    walk(value, analog, found);
+                }
+            }
+            _ => {}
+        }
+    }
 
-            let mut transitions = Vec::new();
-            if file.read_to_end(&mut transitions)? % 8 != 0 {
-                anyhow::bail!("Corrupted file");
+    let mut found = Vec::new();
+    walk(meta, false, &mut found);
+    found
+}
+
+fn register_session_metadata(meta: &Value, analog: &AnalogOptions) {
+    for (name, is_analog, index) in session_channels(meta) {
+        let id = if is_analog {
+            index + analog.base
+        } else {
+            index
+        };
+        channels::register(&name, id as usize);
+    }
+}
+
+type Files = Vec<(String, Option<u64>, Box<dyn Read>)>;
+
+fn session_files(session: &Archive, analog: &AnalogOptions) -> Result<Files> {
+    if let Ok(meta) = session.read_to_string("meta.json") {
+        let meta: Value = serde_json::from_str(&meta).context("Invalid session metadata")?;
+        register_session_metadata(&meta, analog);
+    }
+    session
+        .entries()
+        .iter()
+        .filter(|entry| entry.name.ends_with(".bin"))
+        .map(|entry| Ok((entry.name.clone(), Some(entry.size), session.reader(entry)?)))
+        .collect()
+}
+
+/// A stream holds either a session or a single channel file.
+fn stream_files(mut input: Input, name: &str, analog: &AnalogOptions) -> Result<Files> {
+    if peek(&mut input, ZIP_MAGIC.len())? == ZIP_MAGIC {
+        let session = Archive::from_reader(input).context("Invalid session")?;
+        session_files(&session, analog)
+    } else {
+        Ok(vec![(name.to_owned(), None, Box::new(input))])
+    }
+}
+
+/// Reads an export folder, a session file or a single channel file.
+pub fn new_parser(
+    path: &str,
+    analog: &AnalogOptions,
+) -> Result<LogicData<impl Iterator<Item = Result<(Timestamp, Bits)>>>> {
+    let path = Path::new(path);
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    let metadata = std::fs::metadata(path)?;
+
+    let files = if metadata.is_dir() {
+        let mut files: Files = Vec::new();
+        for entry in std::fs::read_dir(path)? {
+            let entry = entry?;
+
+            // ignore non-files entries
+            let metadata = std::fs::metadata(entry.path())?;
+            if !metadata.is_file() {
+                continue;
             }
 
-            Ok(Some(Channel {
-                id: chan_id,
-                initial_state: initial_state == 1,
-
-                // transitions is known to be a multiple of 8. array_chunks will make this cleaner
-                transitions: transitions
-                    .chunks(8)
-                    .map(move |buf| buf.try_into().map(f64::from_le_bytes).unwrap())
-                    .collect(),
-            }))
-        })
-        .filter_map(Result::transpose)
+            if let Some(file_name) = entry.file_name().to_str() {
+                let file = File::open(entry.path())?;
+                files.push((file_name.to_owned(), Some(metadata.len()), Box::new(file)));
+            }
+        }
+        files
+    } else if metadata.is_file() && Archive::is_archive(path)? {
+        // a session file holds the channel files along with its metadata
+        let session = Archive::open(path).with_context(|| format!("Invalid session {:?}", path))?;
+        session_files(&session, analog)?
+    } else if metadata.is_file() {
+        let file = File::open(path)?;
+        vec![(
+            file_name.to_owned(),
+            Some(metadata.len()),
+            Box::new(file) as _,
+        )]
+    } else {
+        // named pipes
+        stream_files(open_input(path.to_str())?, file_name, analog)?
+    };
+    from_files(files, analog)
+}
+
+/// Reads a session or a single channel file from a stream.
+pub fn new_stream_parser(
+    input: Input,
+    analog: &AnalogOptions,
+) -> Result<LogicData<impl Iterator<Item = Result<(Timestamp, Bits)>>>> {
+    from_files(stream_files(input, "-", analog)?, analog)
+}
+
+fn from_files(
+    files: Files,
+    analog: &AnalogOptions,
+) -> Result<LogicData<impl Iterator<Item = Result<(Timestamp, Bits)>>>> {
+    // display something while processing
+    let progress_bar = ProgressBar::new(0);
+    progress_bar.set_style(
+        ProgressStyle::default_bar()
+            .tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏ ")
+            .template(" {spinner} {msg} {bytes}/{total_bytes} ({eta})"),
+    );
+    progress_bar.set_message("Processing transitions");
+
+    let total_len = files.iter().filter_map(|(_, len, _)| *len).sum();
+    let channels = files
+        .into_iter()
+        .map(|(file_name, len, input)| open_channel(input, len, &file_name, analog, &progress_bar))
         .collect::<Result<Vec<_>, _>>()?;
 
+    let mut seen = HashSet::new();
+    if let Some((id, ..)) = channels.iter().find(|(id, ..)| !seen.insert(*id)) {
+        anyhow::bail!(
+            "Several files map to bit {}, use --analog-base to move the analog channels.",
+            id
+        );
+    }
+
+    // only analog waveforms record the trigger
+    let trigger = channels.iter().find_map(|(_, _, trigger, _)| *trigger);
+
+    progress_bar.set_length(total_len);
+    progress_bar.enable_steady_tick(80);
+
     // compute initial_state
-    let mut current_state = channels.iter().fold(0, |acc, c| {
-        acc | {
-            if c.initial_state {
-                1 << c.id
-            } else {
-                0
-            }
-        }
-    });
-    let mut current_ts = channels
+    let mut current_state = channels
         .iter()
-        .filter_map(|chan| chan.transitions.first().copied())
-        .min_by(|a, b| a.partial_cmp(b).unwrap())
-        .ok_or_else(|| anyhow::anyhow!("No sample found !"))?;
+        .filter(|(_, initial_state, ..)| *initial_state)
+        .map(|(id, ..)| *id as usize)
+        .collect::<Bits>();
 
     // lazily process
-    let transitions = channels
+    let mut transitions = channels
         .into_iter()
-        .map(|channel| {
-            let Channel {
-                id, transitions, ..
-            } = channel;
-            transitions.into_iter().map(move |ts| (id, ts))
+        .map(|(.., channel)| channel)
+        // errors are forwarded as soon as possible
+        .kmerge_by(|a, b| match (a, b) {
+            (Ok((_, a_ts)), Ok((_, b_ts))) => a_ts < b_ts,
+            (a, _) => a.is_err(),
         })
-        .kmerge_by(|(_, a_ts), (_, b_ts)| a_ts < b_ts)
-        .peekable()
-        .batching(move |it| {
-            let mut mask = 0;
-            let mut new_ts = None;
-            it.peeking_take_while(|(id, ts)| {
-                let bit = 1 << id;
+        .peekable();
+    if transitions.peek().is_none() {
+        anyhow::bail!("No sample found !");
+    }
+
+    let transitions = transitions.batching(move |it| {
+        let mut mask = Bits::default();
+        let mut new_ts = None;
+        it.peeking_take_while(|res| match res {
+            Ok((id, ts)) => {
+                let id = *id as usize;
                 let prev_ts = *new_ts.get_or_insert(*ts);
 
                 assert!(prev_ts <= *ts);
-                if (mask & bit) != bit && (ts - prev_ts) < 0.000_000_001 {
-                    mask |= bit;
+                if !mask.get(id) && (*ts - prev_ts) < Timestamp::from_ps(1_000) {
+                    mask.set(id, true);
                     true
                 } else {
                     false
                 }
-            })
-            .for_each(|_| {});
-
-            new_ts.map(|ts| {
-                current_ts = ts;
-                current_state ^= mask;
-                (ts, current_state)
-            })
-        });
+            }
+            Err(_) => false,
+        })
+        .for_each(|_| {});
 
-    Ok(LogicData { transitions })
+        match new_ts {
+            Some(ts) => {
+                current_state ^= &mask;
+                Some(Ok((ts, current_state.clone())))
+            }
+            None => match it.next()? {
+                Err(e) => Some(Err(e)),
+                Ok(_) => unreachable!(),
+            },
+        }
+    });
+
+    Ok(LogicData {
+        transitions,
+        current_ts: Timestamp::ZERO,
+        trigger,
+        progress_bar,
+    })
 }
 
-impl<T: Iterator<Item = (f64, u64)>> Iterator for LogicData<T> {
+impl<T: Iterator<Item = Result<(Timestamp, Bits)>>> Iterator for LogicData<T> {
     type Item = Event;
     fn next(&mut self) -> Option<Self::Item> {
-        let (ts, sample) = self.transitions.next()?;
-        Some((ts, Ok(Box::new(Sample(sample)))))
+        match self.transitions.next() {
+            Some(Ok((ts, sample))) => {
+                self.current_ts = ts;
+                Some((ts, Ok(Box::new(Sample::from(sample)))))
+            }
+            Some(Err(e)) => Some((self.current_ts, Err(e))),
+            None => {
+                self.progress_bar.finish_and_clear();
+                None
+            }
+        }
     }
 }
 
-impl<T: Iterator<Item = (f64, u64)> + 'static> EventIterator for LogicData<T> {
+impl<T: Iterator<Item = Result<(Timestamp, Bits)>> + 'static> EventIterator for LogicData<T> {
     fn into_iterator(self: Box<Self>) -> Box<dyn Iterator<Item = Event>> {
         self
     }
@@ -180,22 +680,106 @@ impl<T: Iterator<Item = (f64, u64)> + 'static> EventIterator for LogicData<T> {
     }
 }
 
-pub fn build(pipeline: &mut Vec<Box<dyn EventIterator>>, args: &Vec<String>) {
+fn parse_threshold(value: &str) -> Result<(Option<u32>, f32)> {
+    let (channel, level) = match value.split_once('=') {
+        Some((channel, level)) => (Some(channel.parse()?), level),
+        None => (None, value),
+    };
+    Ok((channel, level.parse()?))
+}
+
+/// Options of the source, besides its input.
+pub fn options() -> [Arg<'static, 'static>; 3] {
+    [
+        Arg::from_usage(
+            "-t, --threshold [[channel=]level]... 'Analog to digital threshold in volts, for all or a single analog channel.'",
+        )
+        .number_of_values(1),
+        Arg::from_usage("--hysteresis [hysteresis] 'Hysteresis of the analog thresholds in volts.'")
+            .default_value("0.1"),
+        Arg::from_usage("--analog-base [bit] 'Bit of the samples receiving analog channel 0.'")
+            .default_value("0"),
+    ]
+}
+
+pub fn build(pipeline: &mut Vec<Box<dyn EventIterator>>, args: &[String]) {
+    build_from(pipeline, args, None)
+}
+
+/// Same as [`build`], reading from an already opened input.
+pub fn build_from(
+    pipeline: &mut Vec<Box<dyn EventIterator>>,
+    args: &[String],
+    input: Option<Input>,
+) {
     let args = clap::SubCommand::with_name("logic2")
         .setting(clap::AppSettings::NoBinaryName)
-        .arg(
-            Arg::with_name("file")
-                .help("Input file. (may be a folder in case of Saleae Logic 2 exports.)")
-                .required(true),
-        )
+        .args(&options())
+        .arg(Arg::with_name("file").help(
+            "Input folder of a Saleae Logic 2 export, a single channel file or a .sal session file. \
+             Stdin if absent or '-'.",
+        ))
+        .args(&channels::args())
+        .args(&origin::args())
         .get_matches_from(args);
 
-    let parser = Box::new(new_parser(args.value_of("file").unwrap()).unwrap());
-    pipeline.push(parser);
+    let hysteresis = value_t!(args, "hysteresis", f32).unwrap_or_else(|e| e.exit());
+    let mut analog = AnalogOptions {
+        thresholds: HashMap::new(),
+        default: Threshold {
+            level: 1.4,
+            hysteresis,
+        },
+        base: value_t!(args, "analog-base", u32).unwrap_or_else(|e| e.exit()),
+    };
+    for value in args.values_of("threshold").into_iter().flatten() {
+        let (channel, level) = parse_threshold(value)
+            .with_context(|| format!("Invalid threshold {:?}", value))
+            .unwrap();
+        let threshold = Threshold { level, hysteresis };
+        match channel {
+            Some(channel) => {
+                analog.thresholds.insert(channel, threshold);
+            }
+            None => analog.default = threshold,
+        }
+    }
+
+    let (parser, trigger): (Box<dyn EventIterator>, _) = match (input, args.value_of("file")) {
+        (Some(input), _) => {
+            let parser = new_stream_parser(input, &analog)
+                .unwrap_or_else(|e| pipeline::fail(&format!("{:#}", e)));
+            let trigger = parser.trigger;
+            (Box::new(parser), trigger)
+        }
+        (None, None | Some("-")) => {
+            let parser = open_input(None)
+                .and_then(|input| new_stream_parser(input, &analog))
+                .unwrap_or_else(|e| pipeline::fail(&format!("{:#}", e)));
+            let trigger = parser.trigger;
+            (Box::new(parser), trigger)
+        }
+        (None, Some(path)) => {
+            let parser = new_parser(path, &analog).unwrap();
+            let trigger = parser.trigger;
+            (Box::new(parser), trigger)
+        }
+    };
+    channels::register_from(&args).unwrap();
+    pipeline.push(origin::apply(
+        parser,
+        &args,
+        origin::Capture {
+            trigger,
+            initial_state: false,
+        },
+    ));
 }
 
 #[cfg(test)]
 mod test {
+    use crate::test_util::{levels, Trickle};
+
     #[test]
     fn test_incomplete_tag() {
         assert!(super::parse_common_header(b"<SAL").is_err());
@@ -222,4 +806,138 @@ mod test {
             super::parse_digital_header(raw).ok()
         )
     }
+
+    #[test]
+    fn can_parse_analog_header() {
+        #[rustfmt::skip]
+        let raw = &[
+            0, 0, 0, 0, 0, 0, 55, 64,
+            64, 66, 15, 0, 0, 0, 0, 0,
+            2, 0, 0, 0, 0, 0, 0, 0,
+            67, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        assert_eq!(
+            Some((23f64, 1_000_000u64, 2u64, 67u64)),
+            super::parse_analog_header(raw).ok()
+        )
+    }
+
+    #[test]
+    fn can_parse_file_names() {
+        assert_eq!(Some((false, 3)), super::parse_file_name("digital_3.bin"));
+        assert_eq!(Some((true, 12)), super::parse_file_name("analog-12.bin"));
+        assert_eq!(None, super::parse_file_name("meta.json"));
+    }
+
+    #[test]
+    fn can_read_chunked_digital_channel() {
+        let mut raw = b"<SALEAE>\x01\x00\x00\x00\x00\x00\x00\x00".to_vec();
+        raw.extend(&2u64.to_le_bytes());
+        for (initial_state, begin, transitions) in [(0u32, 1., vec![1.5, 2.]), (1, 3., vec![3.5])] {
+            raw.extend(&initial_state.to_le_bytes());
+            raw.extend(&[0; 8]);
+            raw.extend(&f64::to_le_bytes(begin));
+            raw.extend(&[0; 8]);
+            raw.extend(&(transitions.len() as u64).to_le_bytes());
+            transitions
+                .iter()
+                .for_each(|ts: &f64| raw.extend(&ts.to_le_bytes()));
+        }
+
+        let analog = super::AnalogOptions {
+            thresholds: Default::default(),
+            default: super::Threshold {
+                level: 1.4,
+                hysteresis: 0.1,
+            },
+            base: 0,
+        };
+        let len = raw.len() as u64;
+        let progress_bar = indicatif::ProgressBar::hidden();
+        let (id, initial_state, _, channel) = super::open_channel(
+            Box::new(std::io::Cursor::new(raw)),
+            Some(len),
+            "digital-2.bin",
+            &analog,
+            &progress_bar,
+        )
+        .unwrap();
+        assert_eq!((2, false), (id, initial_state));
+        assert_eq!(
+            vec![1.5, 2., 3., 3.5],
+            channel
+                .map(|t| t.unwrap().1.as_secs_f64())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    /// A version 0 digital channel file.
+    fn digital_file(initial_state: u32, transitions: &[f64]) -> Vec<u8> {
+        let mut raw = b"<SALEAE>\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+        raw.extend(&initial_state.to_le_bytes());
+        raw.extend(&[0; 16]);
+        raw.extend(&(transitions.len() as u64).to_le_bytes());
+        transitions
+            .iter()
+            .for_each(|ts| raw.extend(&ts.to_le_bytes()));
+        raw
+    }
+
+    #[test]
+    fn can_stream_partially_read_channels() {
+        let analog = super::AnalogOptions {
+            thresholds: Default::default(),
+            default: super::Threshold {
+                level: 1.4,
+                hysteresis: 0.1,
+            },
+            base: 0,
+        };
+        let files: super::Files = vec![
+            (
+                "digital_0.bin".to_owned(),
+                None,
+                Box::new(Trickle::new(digital_file(0, &[1., 3.]), 3)),
+            ),
+            (
+                "digital_1.bin".to_owned(),
+                None,
+                Box::new(Trickle::new(digital_file(1, &[2.]), 5)),
+            ),
+        ];
+        let parser = super::from_files(files, &analog).unwrap();
+        assert_eq!(vec![(1., 0b11), (2., 0b01), (3., 0b00)], levels(parser));
+
+        // a single channel file on a stream is read as channel 0
+        let input = Trickle::new(digital_file(1, &[0.5, 1.5]), 7);
+        let parser =
+            super::new_stream_parser(Box::new(std::io::BufReader::new(input)), &analog).unwrap();
+        assert_eq!(vec![(0.5, 0), (1.5, 1)], levels(parser));
+    }
+
+    #[test]
+    fn can_find_session_channels() {
+        let meta = serde_json::json!({
+            "data": {
+                "digitalChannels": [{ "index": 0, "name": "TX" }, { "index": 1, "name": "" }],
+                "analogChannels": [{ "channel": { "index": 2 }, "name": "VBUS" }],
+            }
+        });
+        assert_eq!(
+            vec![("VBUS".to_owned(), true, 2), ("TX".to_owned(), false, 0)],
+            super::session_channels(&meta)
+        );
+    }
+
+    #[test]
+    fn threshold_has_hysteresis() {
+        let threshold = super::Threshold {
+            level: 1.5,
+            hysteresis: 0.2,
+        };
+        assert!(!threshold.update(false, 1.55));
+        assert!(threshold.update(false, 1.65));
+        assert!(threshold.update(true, 1.45));
+        assert!(!threshold.update(true, 1.35));
+    }
 }
@@ -0,0 +1,206 @@
+//! Time origin of the samples, shared by the sources.
+//!
+//! Sources produce the times found in their capture: a time within the file for VCD and Logic 2,
+//! a sample count over the frequency for Logic 1 and sigrok. `--origin` moves them so that the
+//! captures line up with each other and with what the analyzer software shows.
+
+use std::collections::VecDeque;
+use std::str::FromStr;
+
+use clap::{Arg, ArgMatches};
+
+use super::Sample;
+use crate::pipeline::{self, Event, EventIterator, Timestamp};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Origin {
+    /// Times as found in the capture.
+    File,
+    /// The first change of any channel is at 0.
+    FirstEdge,
+    /// The trigger marker of the capture is at 0.
+    Trigger,
+    /// This time of the capture is at 0.
+    At(Timestamp),
+}
+
+impl FromStr for Origin {
+    type Err = anyhow::Error;
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Ok(match value {
+            "file" => Origin::File,
+            "first-edge" => Origin::FirstEdge,
+            "trigger" => Origin::Trigger,
+            time => Origin::At(pipeline::parse_time(time)?),
+        })
+    }
+}
+
+/// Option choosing the time origin, shared by the sources.
+pub fn args() -> [Arg<'static, 'static>; 1] {
+    [Arg::from_usage(
+        "--origin [origin] 'Time origin: file (times of the capture), first-edge, trigger or a time of the capture.'",
+    )
+    .default_value("file")
+    .allow_hyphen_values(true)]
+}
+
+/// What a source knows of its capture.
+pub struct Capture {
+    /// Time of the trigger marker, if the capture has one.
+    pub trigger: Option<Timestamp>,
+    /// Whether the capture starts with the initial state of the channels (e.g. the `#0` values of
+    /// a VCD file) rather than with their first change.
+    pub initial_state: bool,
+}
+
+/// Moves the samples of a source.
+pub struct Rebase {
+    it: Box<dyn Iterator<Item = Event>>,
+    origin: Option<Timestamp>,
+    initial_state: bool,
+    /// Events read while looking for the first edge.
+    pending: VecDeque<Event>,
+}
+
+impl Rebase {
+    pub fn new(
+        it: Box<dyn Iterator<Item = Event>>,
+        origin: Option<Timestamp>,
+        initial_state: bool,
+    ) -> Self {
+        Self {
+            it,
+            origin,
+            initial_state,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Reads the source up to its first edge, the origin falling back on the first sample for
+    /// captures without any change.
+    ///
+    /// The initial state is the one reached after all the samples of the first time, sources
+    /// giving it with one sample per channel.
+    fn find_first_edge(&mut self) -> Timestamp {
+        let mut initial: Option<(Timestamp, Sample)> = None;
+        for (ts, ev) in self.it.by_ref() {
+            let sample = ev
+                .as_ref()
+                .ok()
+                .map(|ev| pipeline::downcast_ref::<Sample>(ev).clone());
+            self.pending.push_back((ts, ev));
+            let sample = match sample {
+                Some(sample) => sample,
+                None => continue,
+            };
+            match &initial {
+                None if !self.initial_state => return ts,
+                Some((start, state)) if *start != ts && *state != sample => return ts,
+                Some((start, _)) if *start != ts => {}
+                _ => initial = Some((ts, sample)),
+            }
+        }
+        initial.map_or(Timestamp::ZERO, |(ts, _)| ts)
+    }
+}
+
+impl Iterator for Rebase {
+    type Item = Event;
+    fn next(&mut self) -> Option<Self::Item> {
+        let origin = match self.origin {
+            Some(origin) => origin,
+            None => {
+                let origin = self.find_first_edge();
+                self.origin = Some(origin);
+                origin
+            }
+        };
+        let (ts, ev) = self.pending.pop_front().or_else(|| self.it.next())?;
+        Some((ts - origin, ev))
+    }
+}
+
+impl EventIterator for Rebase {
+    fn into_iterator(self: Box<Self>) -> Box<dyn Iterator<Item = Event>> {
+        self
+    }
+    fn event_type(&self) -> std::any::TypeId {
+        std::any::TypeId::of::<Sample>()
+    }
+    fn event_type_name(&self) -> &'static str {
+        std::any::type_name::<Sample>()
+    }
+}
+
+/// Applies the origin chosen on the command line to a source.
+pub fn apply(
+    source: Box<dyn EventIterator>,
+    matches: &ArgMatches<'_>,
+    capture: Capture,
+) -> Box<dyn EventIterator> {
+    let origin = matches
+        .value_of("origin")
+        .unwrap_or("file")
+        .parse()
+        .unwrap_or_else(|e| clap::Error::value_validation_auto(format!("origin: {}", e)).exit());
+    let origin = match origin {
+        Origin::File => return source,
+        Origin::FirstEdge => None,
+        Origin::Trigger => Some(capture.trigger.unwrap_or_else(|| {
+            clap::Error::value_validation_auto("origin: the capture has no trigger marker".into())
+                .exit()
+        })),
+        Origin::At(ts) => Some(ts),
+    };
+    Box::new(Rebase::new(
+        source.into_iterator(),
+        origin,
+        capture.initial_state,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::Rebase;
+    use crate::source::vcd::VcdParser;
+    use crate::test_util::{levels, samples};
+
+    #[test]
+    fn moves_the_first_edge_to_zero() {
+        let input = samples(&[(1., 0b01), (2., 0b01), (3., 0b11)]);
+        assert_eq!(
+            vec![(-2., 0b01), (-1., 0b01), (0., 0b11)],
+            levels(Rebase::new(input, None, true))
+        );
+    }
+
+    #[test]
+    fn skips_the_initial_values_of_vcd_files() {
+        let trace = "$timescale 1us $end
+$var wire 1 ! a $end
+$var wire 1 \" b $end
+$enddefinitions $end
+#0
+0!
+1\"
+#100
+1!
+";
+        let input = Box::new(VcdParser::new(trace.as_bytes(), vec![]));
+        assert_eq!(
+            vec![(-100e-6, 0b00), (-100e-6, 0b10), (0., 0b11)],
+            levels(Rebase::new(input, None, true))
+        );
+    }
+
+    #[test]
+    fn takes_the_first_transition_of_logic2_captures() {
+        // Logic 2 only records the transitions
+        let input = samples(&[(1., 0b01), (2., 0b11)]);
+        assert_eq!(
+            vec![(0., 0b01), (1., 0b11)],
+            levels(Rebase::new(input, None, false))
+        );
+    }
+}
@@ -0,0 +1,313 @@
+//! Session files (`.sr`) saved by sigrok-cli and PulseView.
+//!
+//! A session is a zip archive holding a `metadata` file and the raw logic samples, split in
+//! `logic-1-N` chunks (or a single `logic-1` file for older versions).
+
+use std::io::{BufReader, ErrorKind, Read};
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use clap::Arg;
+
+use super::archive::Archive;
+use super::{channels, origin, Bits, Input, Sample};
+use crate::pipeline::{self, Event, EventIterator, Timestamp};
+
+#[derive(Debug, PartialEq)]
+struct Metadata {
+    capture_file: String,
+    sample_rate: f64,
+    unit_size: usize,
+    total_probes: usize,
+    /// Bit and name of the named probes.
+    probes: Vec<(usize, String)>,
+}
+
+/// Parses a sample rate such as `24 MHz`.
+fn parse_sample_rate(value: &str) -> Result<f64> {
+    let value = value.trim();
+    let value = value.strip_suffix("Hz").unwrap_or(value).trim_end();
+    let (value, scale) = match value.chars().last() {
+        Some('k') => (&value[..value.len() - 1], 1e3),
+        Some('M') => (&value[..value.len() - 1], 1e6),
+        Some('G') => (&value[..value.len() - 1], 1e9),
+        _ => (value, 1.),
+    };
+    let rate = value
+        .trim()
+        .parse::<f64>()
+        .with_context(|| format!("Invalid sample rate {:?}", value))?;
+    Ok(rate * scale)
+}
+
+fn parse_metadata(metadata: &str) -> Result<Metadata> {
+    let mut capture_file = None;
+    let mut sample_rate = None;
+    let mut unit_size = 1;
+    let mut total_probes = None;
+    let mut probes = Vec::new();
+
+    // only the first device holds logic samples
+    let mut in_device = false;
+    for line in metadata.lines().map(str::trim) {
+        if line.starts_with('[') {
+            if in_device {
+                break;
+            }
+            in_device = line.starts_with("[device");
+            continue;
+        }
+        let (key, value) = match line.split_once('=') {
+            Some(entry) if in_device => entry,
+            _ => continue,
+        };
+        match key.trim() {
+            "capturefile" => capture_file = Some(value.trim().to_owned()),
+            "samplerate" => sample_rate = Some(parse_sample_rate(value)?),
+            "unitsize" => unit_size = value.trim().parse().context("Invalid unit size")?,
+            "total probes" => {
+                total_probes = Some(value.trim().parse().context("Invalid probe count")?)
+            }
+            key => {
+                if let Some(index) = key.strip_prefix("probe") {
+                    let index: usize = index.parse().context("Invalid probe index")?;
+                    anyhow::ensure!(index != 0, "Invalid probe index");
+                    probes.push((index - 1, value.trim().to_owned()));
+                }
+            }
+        }
+    }
+
+    anyhow::ensure!(unit_size != 0, "Invalid unit size");
+    Ok(Metadata {
+        capture_file: capture_file.ok_or_else(|| anyhow!("No logic capture in the session"))?,
+        sample_rate: sample_rate.ok_or_else(|| anyhow!("Missing samplerate"))?,
+        unit_size,
+        total_probes: total_probes.unwrap_or(unit_size * 8),
+        probes,
+    })
+}
+
+pub struct SigrokParser {
+    archive: Archive,
+    /// Entries holding the chunks left to read.
+    chunks: std::vec::IntoIter<usize>,
+    input: Option<BufReader<Box<dyn Read>>>,
+
+    buffer: Vec<u8>,
+    total_probes: usize,
+    sample_rate: f64,
+    index: i64,
+    last: Option<Bits>,
+    current_ts: Timestamp,
+}
+
+impl SigrokParser {
+    pub fn new(archive: Archive) -> Result<Self> {
+        let metadata = parse_metadata(&archive.read_to_string("metadata")?)?;
+        anyhow::ensure!(metadata.sample_rate > 0., "Invalid sample rate");
+
+        for (bit, name) in &metadata.probes {
+            channels::register(name, *bit);
+        }
+
+        // chunks must be read in order: `logic-1` then `logic-1-1`, `logic-1-2`...
+        let mut chunks = archive
+            .entries()
+            .iter()
+            .enumerate()
+            .filter_map(|(i, entry)| {
+                let suffix = entry.name.strip_prefix(&metadata.capture_file)?;
+                if suffix.is_empty() {
+                    Some((0, i))
+                } else {
+                    Some((suffix.strip_prefix('-')?.parse::<u64>().ok()?, i))
+                }
+            })
+            .collect::<Vec<_>>();
+        chunks.sort_unstable();
+
+        Ok(Self {
+            archive,
+            chunks: chunks
+                .into_iter()
+                .map(|(_, i)| i)
+                .collect::<Vec<_>>()
+                .into_iter(),
+            input: None,
+            buffer: vec![0; metadata.unit_size],
+            total_probes: metadata.total_probes,
+            sample_rate: metadata.sample_rate,
+            index: 0,
+            last: None,
+            current_ts: Timestamp::ZERO,
+        })
+    }
+
+    /// Reads the next sample, moving to the next chunk when needed.
+    fn read_sample(&mut self) -> Result<Option<Bits>> {
+        let unit_size = self.buffer.len();
+        loop {
+            let input = match &mut self.input {
+                Some(input) => input,
+                None => match self.chunks.next() {
+                    Some(i) => {
+                        let reader = self.archive.reader(&self.archive.entries()[i])?;
+                        self.input.insert(BufReader::new(reader))
+                    }
+                    None => return Ok(None),
+                },
+            };
+
+            let mut len = 0;
+            while len < unit_size {
+                match input.read(&mut self.buffer[len..]) {
+                    Ok(0) => break,
+                    Ok(n) => len += n,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            match len {
+                0 => self.input = None,
+                len if len == unit_size => return Ok(Some(Bits::from_le_bytes(&self.buffer))),
+                _ => anyhow::bail!("Truncated sample"),
+            }
+        }
+    }
+}
+
+impl Iterator for SigrokParser {
+    type Item = Event;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let mut levels = match self.read_sample() {
+                Ok(levels) => levels?,
+                Err(e) => {
+                    self.input = None;
+                    self.chunks = Vec::new().into_iter();
+                    return Some((self.current_ts, Err(e)));
+                }
+            };
+            let ts = Timestamp::from_sample(self.index, self.sample_rate);
+            self.index += 1;
+
+            levels.truncate(self.total_probes);
+            if self.last.as_ref() != Some(&levels) {
+                self.last = Some(levels.clone());
+                self.current_ts = ts;
+                return Some((ts, Ok(Box::new(Sample::from(levels)))));
+            }
+        }
+    }
+}
+
+impl EventIterator for SigrokParser {
+    fn into_iterator(self: Box<Self>) -> Box<dyn Iterator<Item = Event>> {
+        self
+    }
+    fn event_type(&self) -> std::any::TypeId {
+        std::any::TypeId::of::<super::Sample>()
+    }
+    fn event_type_name(&self) -> &'static str {
+        std::any::type_name::<super::Sample>()
+    }
+}
+
+pub fn build(pipeline: &mut Vec<Box<dyn EventIterator>>, args: &[String]) {
+    build_from(pipeline, args, None)
+}
+
+/// Same as [`build`], reading the session from an already opened input.
+pub fn build_from(
+    pipeline: &mut Vec<Box<dyn EventIterator>>,
+    args: &[String],
+    input: Option<Input>,
+) {
+    let args = clap::SubCommand::with_name("sigrok")
+        .setting(clap::AppSettings::NoBinaryName)
+        .args(&[
+            Arg::with_name("file").help("Input sigrok session (.sr) file, stdin if absent or '-'.")
+        ])
+        .args(&channels::args())
+        .args(&origin::args())
+        .get_matches_from(args);
+
+    // sessions are zip files, streams have to be loaded in memory to be read
+    let file = args.value_of("file");
+    let archive = match (input, file) {
+        (None, Some(path)) if Path::new(path).is_file() => Archive::open(Path::new(path)),
+        (Some(input), _) => Archive::from_reader(input),
+        (None, file) => super::open_input(file).and_then(Archive::from_reader),
+    };
+    let parser = archive
+        .and_then(SigrokParser::new)
+        .context("Opening the sigrok session")
+        .unwrap_or_else(|e| pipeline::fail(&format!("{:#}", e)));
+    channels::register_from(&args).unwrap();
+    pipeline.push(origin::apply(
+        Box::new(parser),
+        &args,
+        origin::Capture {
+            trigger: None,
+            initial_state: true,
+        },
+    ));
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test_util::{levels, zip, Trickle};
+
+    #[test]
+    fn can_parse_sample_rates() {
+        assert_eq!(24e6, super::parse_sample_rate("24 MHz").unwrap());
+        assert_eq!(500e3, super::parse_sample_rate("500 kHz").unwrap());
+        assert_eq!(200., super::parse_sample_rate("200 Hz").unwrap());
+        assert!(super::parse_sample_rate("fast").is_err());
+    }
+
+    #[test]
+    fn can_parse_metadata() {
+        let metadata = "[global]\n\
+                        sigrok version=0.5.2\n\
+                        \n\
+                        [device 1]\n\
+                        capturefile=logic-1\n\
+                        total probes=3\n\
+                        samplerate=12 MHz\n\
+                        total analog=0\n\
+                        probe1=TX\n\
+                        probe3=RX\n\
+                        unitsize=1\n";
+        assert_eq!(
+            super::Metadata {
+                capture_file: "logic-1".to_owned(),
+                sample_rate: 12e6,
+                unit_size: 1,
+                total_probes: 3,
+                probes: vec![(0, "TX".to_owned()), (2, "RX".to_owned())],
+            },
+            super::parse_metadata(metadata).unwrap()
+        );
+    }
+
+    #[test]
+    fn reads_the_chunks_in_order() {
+        let metadata =
+            "[device 1]\ncapturefile=logic-1\ntotal probes=2\nsamplerate=1 Hz\nunitsize=1\n";
+        // chunks are not stored in order
+        let data = zip(&[
+            ("metadata", metadata.as_bytes()),
+            ("logic-1-2", &[3, 0]),
+            ("logic-1", &[0, 0, 1]),
+            ("logic-1-1", &[1, 0xff]),
+        ]);
+        let archive = super::Archive::from_reader(Trickle::new(data, 5)).unwrap();
+        // the unused probes are masked
+        assert_eq!(
+            vec![(0., 0), (2., 1), (4., 3), (6., 0)],
+            levels(super::SigrokParser::new(archive).unwrap())
+        );
+    }
+}
@@ -1,22 +1,112 @@
 use std::collections::BTreeMap;
-use std::io::Read;
+use std::io::{BufRead, BufReader, Read};
 
 use anyhow::{anyhow, Context};
+use clap::Arg;
 use vcd::{Command, IdCode, Parser, TimescaleUnit, Value, VarType};
 
-use super::Sample;
-use crate::pipeline::{Event, EventData, EventIterator};
+use super::{channels, open_input, origin, Input, Sample};
+use crate::pipeline::{self, Event, EventData, EventIterator, Timestamp};
+
+/// The vcd crate expects `$var <type> <size> <id> <reference> $end` and bails on the
+/// `<reference> [msb:lsb]` form emitted by most simulators. This adapter glues the bit range to the
+/// reference so that it is read as a single token.
+struct VarDefFixup<T> {
+    input: BufReader<T>,
+    buffer: Vec<u8>,
+    pos: usize,
+
+    token: Vec<u8>,
+    in_var: bool,
+    pending_ws: Vec<u8>,
+}
+
+impl<T: Read> VarDefFixup<T> {
+    fn new(input: T) -> Self {
+        Self {
+            input: BufReader::new(input),
+            buffer: Vec::new(),
+            pos: 0,
+            token: Vec::new(),
+            in_var: false,
+            pending_ws: Vec::new(),
+        }
+    }
+
+    fn fill(&mut self) -> std::io::Result<()> {
+        self.buffer.clear();
+        self.pos = 0;
+        while self.buffer.is_empty() {
+            let chunk = self.input.fill_buf()?;
+            if chunk.is_empty() {
+                self.buffer.append(&mut self.pending_ws);
+                return Ok(());
+            }
+            for &b in chunk {
+                if b.is_ascii_whitespace() {
+                    match self.token.as_slice() {
+                        b"$var" => self.in_var = true,
+                        b"$end" => self.in_var = false,
+                        _ => {}
+                    }
+                    self.token.clear();
+                    if self.in_var {
+                        self.pending_ws.push(b);
+                    } else {
+                        self.buffer.append(&mut self.pending_ws);
+                        self.buffer.push(b);
+                    }
+                } else {
+                    if !(self.in_var && b == b'[') {
+                        self.buffer.append(&mut self.pending_ws);
+                    }
+                    self.pending_ws.clear();
+                    self.token.push(b);
+                    self.buffer.push(b);
+                }
+            }
+            let len = chunk.len();
+            self.input.consume(len);
+        }
+        Ok(())
+    }
+}
+
+impl<T: Read> Read for VarDefFixup<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos == self.buffer.len() {
+            self.fill()?;
+        }
+        let len = buf.len().min(self.buffer.len() - self.pos);
+        buf[..len].copy_from_slice(&self.buffer[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+#[derive(Debug)]
+struct Var {
+    id: IdCode,
+    name: String,
+    width: u32,
+}
 
 pub struct VcdParser<T>
 where
     T: Read,
 {
-    input: Parser<T>,
-    factor: f64,
-    first_ts: f64,
-    current_ts: f64,
-    vars: BTreeMap<IdCode, usize>,
-    state: u64,
+    input: Parser<VarDefFixup<T>>,
+    /// Count and denominator of the time unit, `(10, 1_000_000_000)` for `10ns`.
+    timescale: (i64, i64),
+    current_ts: Timestamp,
+
+    mapping: Vec<(String, usize)>,
+    scope: Vec<String>,
+    declared: Vec<Var>,
+    /// Maps a variable to its (lsb position, width) in the sample. A single IdCode may be aliased
+    /// by several variables.
+    vars: BTreeMap<IdCode, Vec<(usize, u32)>>,
+    state: Sample,
     stopped: bool,
 }
 
@@ -24,17 +114,233 @@ impl<T> VcdParser<T>
 where
     T: Read,
 {
-    pub fn new(input: T) -> Self {
+    /// `mapping` assigns signals to the bit of the samples holding their lsb. When empty, all
+    /// signals are mapped in their declaration order.
+    pub fn new(input: T, mapping: Vec<(String, usize)>) -> Self {
         Self {
-            input: Parser::new(input),
-            factor: 1.,
-            first_ts: -0.1, // pre-trigger buffer size
-            current_ts: -0.1,
+            input: Parser::new(VarDefFixup::new(input)),
+            timescale: (1, 1),
+            current_ts: Timestamp::ZERO,
+            mapping,
+            scope: Vec::new(),
+            declared: Vec::new(),
             vars: BTreeMap::new(),
-            state: 0,
+            state: Sample::default(),
             stopped: false,
         }
     }
+
+    fn assign(&mut self, var: &Var, lsb: usize) -> anyhow::Result<()> {
+        for name in [var.name.as_str(), leaf(&var.name)] {
+            channels::register(name, lsb);
+            if var.width > 1 {
+                for bit in 0..var.width as usize {
+                    channels::register(&format!("{}[{}]", name, bit), lsb + bit);
+                }
+            }
+        }
+        self.vars.entry(var.id).or_default().push((lsb, var.width));
+        Ok(())
+    }
+
+    /// Resolves the declared variables to their position in the samples.
+    fn map_vars(&mut self) -> anyhow::Result<()> {
+        let declared = std::mem::take(&mut self.declared);
+
+        if self.mapping.is_empty() {
+            // Saleae exports name their channels `D_<n>`: keep the original channel numbers.
+            let saleae: Option<Vec<usize>> = declared
+                .iter()
+                .map(|var| {
+                    leaf(&var.name)
+                        .strip_prefix("D_")
+                        .and_then(|n| n.parse().ok())
+                        .filter(|_| var.width == 1)
+                })
+                .collect();
+
+            match saleae {
+                Some(channels) => {
+                    for (var, lsb) in declared.iter().zip(channels) {
+                        self.assign(var, lsb)?;
+                    }
+                }
+                None => {
+                    let mut lsb = 0;
+                    for var in &declared {
+                        self.assign(var, lsb)?;
+                        lsb += var.width as usize;
+                    }
+                }
+            }
+            return Ok(());
+        }
+
+        let mapping = std::mem::take(&mut self.mapping);
+        let mut missing = Vec::new();
+        for (name, lsb) in &mapping {
+            let mut found = false;
+            for var in declared.iter().filter(|var| matches(&var.name, name)) {
+                self.assign(var, *lsb)?;
+                found = true;
+            }
+            if !found {
+                missing.push(name.as_str());
+            }
+        }
+        anyhow::ensure!(missing.is_empty(), "Signal(s) not found: {:?}", missing);
+        Ok(())
+    }
+
+    /// Processes a command, returning the event it produces if any.
+    fn handle(&mut self, cmd: Command) -> Option<Event> {
+        match cmd {
+            Command::Timescale(n, unit) => {
+                let den = match unit {
+                    TimescaleUnit::S => 1,
+                    TimescaleUnit::MS => 1_000,
+                    TimescaleUnit::US => 1_000_000,
+                    TimescaleUnit::NS => 1_000_000_000,
+                    TimescaleUnit::PS => 1_000_000_000_000,
+                    TimescaleUnit::FS => 1_000_000_000_000_000,
+                };
+                self.timescale = (i64::from(n), den);
+            }
+            Command::Timestamp(ts) => {
+                let (n, den) = self.timescale;
+                let new_ts = Timestamp::from_fraction(ts as i64, n, den);
+                if self.current_ts > new_ts {
+                    self.stopped = true;
+                    return Some((self.current_ts, Err(anyhow!("Timestamp must be monotonic"))));
+                }
+                self.current_ts = new_ts;
+            }
+            Command::ScopeDef(_, name) => self.scope.push(name),
+            Command::Upscope => {
+                self.scope.pop();
+            }
+            Command::VarDef(
+                VarType::Wire
+                | VarType::Reg
+                | VarType::Tri
+                | VarType::Tri0
+                | VarType::Tri1
+                | VarType::TriAnd
+                | VarType::TriOr
+                | VarType::TriReg
+                | VarType::WAnd
+                | VarType::WOr
+                | VarType::Supply0
+                | VarType::Supply1
+                | VarType::Integer,
+                size,
+                id,
+                reference,
+            ) => {
+                let mut name = self.scope.join(".");
+                if !name.is_empty() {
+                    name.push('.');
+                }
+                name.push_str(strip_range(&reference));
+                self.declared.push(Var {
+                    id,
+                    name,
+                    width: size,
+                });
+            }
+            Command::Enddefinitions => {
+                if let Err(e) = self.map_vars() {
+                    self.stopped = true;
+                    return Some((self.current_ts, Err(e)));
+                }
+            }
+            Command::ChangeScalar(id, v) if self.vars.contains_key(&id) => {
+                self.update(id, &[v]);
+                return Some((
+                    self.current_ts,
+                    Ok(Box::new(self.state.clone()) as Box<dyn EventData>),
+                ));
+            }
+            Command::ChangeVector(id, v) if self.vars.contains_key(&id) => {
+                self.update(id, &v);
+                return Some((
+                    self.current_ts,
+                    Ok(Box::new(self.state.clone()) as Box<dyn EventData>),
+                ));
+            }
+            // changes to unmapped signals and real, event, time & parameters definitions are
+            // ignored as they cannot be represented in a sample.
+            _v => {
+                //eprintln!("ignoring: {:?}", v);
+            }
+        }
+        None
+    }
+
+    /// Reads the definitions, up to `$enddefinitions`, so that the signals are mapped and named
+    /// before the first change is read.
+    pub fn read_header(&mut self) -> anyhow::Result<()> {
+        loop {
+            match self.input.next() {
+                Some(Ok(Command::Enddefinitions)) => {
+                    self.map_vars()?;
+                    return Ok(());
+                }
+                Some(Ok(cmd)) => {
+                    if let Some((_, Err(e))) = self.handle(cmd) {
+                        return Err(e);
+                    }
+                }
+                Some(Err(err)) => return Err(anyhow!("{:?}", err)),
+                None => anyhow::bail!("Missing $enddefinitions"),
+            }
+        }
+    }
+
+    fn update(&mut self, id: IdCode, values: &[Value]) {
+        let positions = &self.vars[&id];
+
+        // values are msb first and may be shorter than the variable, in which case they are
+        // left-extended with 0, or with x/z if the leftmost value is x/z.
+        let extension = match values.first() {
+            Some(Value::X) => Value::X,
+            Some(Value::Z) => Value::Z,
+            _ => Value::V0,
+        };
+        for &(lsb, width) in positions {
+            for bit in 0..width as usize {
+                let value = match values.len().checked_sub(bit + 1) {
+                    Some(index) => values[index],
+                    None => extension,
+                };
+                let channel = lsb + bit;
+                self.state.levels.set(channel, value == Value::V1);
+                self.state.unknown.set(channel, value == Value::X);
+                self.state.high_z.set(channel, value == Value::Z);
+            }
+        }
+    }
+}
+
+/// Removes the `[msb:lsb]` suffix from vector references.
+fn strip_range(reference: &str) -> &str {
+    match reference.find('[') {
+        Some(idx) if reference[idx..].contains(':') => &reference[..idx],
+        _ => reference,
+    }
+}
+
+fn leaf(name: &str) -> &str {
+    name.rsplit('.').next().unwrap_or(name)
+}
+
+/// A signal matches if its full hierarchical name ends with `pattern`.
+fn matches(name: &str, pattern: &str) -> bool {
+    name == pattern
+        || name
+            .strip_suffix(pattern)
+            .map(|prefix| prefix.ends_with('.'))
+            .unwrap_or(false)
 }
 
 impl<T> Iterator for VcdParser<T>
@@ -49,68 +355,11 @@ where
 
         let out = loop {
             match self.input.next()? {
-                Ok(cmd) => match cmd {
-                    Command::Timescale(n, unit) => {
-                        let unit = match unit {
-                            TimescaleUnit::S => 1.,
-                            TimescaleUnit::MS => 0.001,
-                            TimescaleUnit::US => 0.000_001,
-                            TimescaleUnit::NS => 0.000_000_001,
-                            TimescaleUnit::PS => 0.000_000_000_001,
-                            TimescaleUnit::FS => 0.000_000_000_000_001,
-                        };
-                        self.factor = (n as f64) * unit;
-                    }
-                    Command::Timestamp(ts) => {
-                        let new_ts = (ts as f64) * self.factor;
-                        if self.first_ts == -0.1 {
-                            self.first_ts = new_ts;
-                        }
-
-                        let new_ts = new_ts - self.first_ts - 0.1;
-                        if self.current_ts > new_ts {
-                            self.stopped = true;
-                            break (self.current_ts, Err(anyhow!("Timestamp must be monotonic")));
-                        }
-                        self.current_ts = new_ts;
-                    }
-                    Command::ChangeScalar(id, v) => {
-                        let v = match v {
-                            Value::V0 => 0,
-                            Value::V1 => 1,
-                            _ => {
-                                self.stopped = true;
-                                break (
-                                    self.current_ts,
-                                    Err(anyhow!("Unsupported value : {:?}", v)),
-                                );
-                            }
-                        };
-                        let shift = self.vars[&id];
-                        self.state &= !(1 << shift);
-                        self.state |= v << shift;
-                        break (
-                            self.current_ts,
-                            Ok(Box::new(Sample(self.state)) as Box<dyn EventData>),
-                        );
+                Ok(cmd) => {
+                    if let Some(event) = self.handle(cmd) {
+                        break event;
                     }
-                    Command::VarDef(ty, _sz, id, name) => {
-                        if ty == VarType::Wire {
-                            self.vars.insert(
-                                id,
-                                name.split('_').nth(1).unwrap().parse::<usize>().unwrap(),
-                            );
-                        } else {
-                            break (
-                                self.current_ts,
-                                Err(anyhow!("Unsupported VarType: {:?}", ty)),
-                            );
-                        }
-                    }
-                    _v => {
-                        //eprintln!("ignoring: {:?}", v);
-                    }
-                },
+                }
                 Err(err) => break (self.current_ts, Err(anyhow!("{:?}", err))),
             }
         };
@@ -130,23 +379,170 @@ impl<T: Read + 'static> EventIterator for VcdParser<T> {
     }
 }
 
-pub fn build(pipeline: &mut Vec<Box<dyn EventIterator>>, args: &Vec<String>) {
-    use clap::Arg;
-    let _args = clap::SubCommand::with_name("logic2")
+/// Options of the source, besides its input.
+pub fn options() -> [Arg<'static, 'static>; 1] {
+    [Arg::from_usage(
+        "-m, --map [signal=bit]... 'Maps a signal (or the lsb of a vector) to a bit of the samples.'",
+    )
+    .number_of_values(1)]
+}
+
+pub fn build(pipeline: &mut Vec<Box<dyn EventIterator>>, args: &[String]) {
+    build_from(pipeline, args, None)
+}
+
+/// Same as [`build`], reading from an already opened input.
+pub fn build_from(
+    pipeline: &mut Vec<Box<dyn EventIterator>>,
+    args: &[String],
+    input: Option<Input>,
+) {
+    let args = clap::SubCommand::with_name("vcd")
         .setting(clap::AppSettings::NoBinaryName)
-        .args(&[Arg::with_name("file")
-            .help("Input file. (may be a folder in case of Saleae Logic 2 exports.)")
-            .required(true)])
+        .args(&options())
+        .arg(Arg::with_name("file").help("Input file, stdin if absent or '-'."))
+        .args(&channels::args())
+        .args(&origin::args())
         .get_matches_from(args);
 
-    let file = std::fs::File::open(
-        _args
-            .value_of("file")
-            .context("Fetching file argument")
-            .unwrap(),
-    )
-    .context("Openning capture file.")
-    .unwrap();
-    let parser = Box::new(VcdParser::new(file));
-    pipeline.push(parser);
+    let mapping = args
+        .values_of("map")
+        .into_iter()
+        .flatten()
+        .map(|map| {
+            let (name, bit) = map
+                .rsplit_once('=')
+                .with_context(|| format!("Invalid mapping {:?}, expected <signal>=<bit>", map))?;
+            let bit = bit
+                .parse()
+                .with_context(|| format!("Invalid bit index in {:?}", map))?;
+            Ok((name.to_string(), bit))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()
+        .unwrap();
+
+    let input = input
+        .map_or_else(|| open_input(args.value_of("file")), Ok)
+        .context("Opening the capture")
+        .unwrap_or_else(|e| pipeline::fail(&format!("{:#}", e)));
+    let mut parser = Box::new(VcdParser::new(input, mapping));
+    // signal names must be known before the decoders are built
+    parser.read_header().unwrap();
+    channels::register_from(&args).unwrap();
+    pipeline.push(origin::apply(
+        parser,
+        &args,
+        origin::Capture {
+            trigger: None,
+            initial_state: true,
+        },
+    ));
+}
+
+#[cfg(test)]
+mod test {
+    use super::VcdParser;
+    use crate::pipeline;
+    use crate::source::{Bits, Sample};
+
+    const TRACE: &str = "$timescale 1ns $end
+$scope module top $end
+$var wire 1 ! clk $end
+$scope module dut $end
+$var reg 4 \" data [3:0] $end
+$upscope $end
+$upscope $end
+$enddefinitions $end
+#0
+0!
+b0 \"
+#10
+1!
+b101 \"
+";
+
+    fn samples(mapping: Vec<(String, usize)>) -> Vec<u64> {
+        VcdParser::new(TRACE.as_bytes(), mapping)
+            .map(|(_, ev)| {
+                pipeline::downcast::<Sample>(ev.unwrap())
+                    .levels
+                    .as_u64()
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn maps_signals_in_declaration_order() {
+        assert_eq!(vec![0b00000, 0b00000, 0b00001, 0b01011], samples(vec![]));
+    }
+
+    #[test]
+    fn maps_signals_by_name() {
+        let mapping = vec![("dut.data".to_string(), 4), ("top.clk".to_string(), 1)];
+        assert_eq!(vec![0, 0, 0b10, 0b101_0010], samples(mapping));
+    }
+
+    #[test]
+    fn carries_undefined_states() {
+        let trace = "$var wire 1 ! clk $end
+$var wire 4 \" data [3:0] $end
+$enddefinitions $end
+#0
+x!
+bz \"
+#10
+1!
+b1x \"
+";
+        let samples: Vec<_> = VcdParser::new(trace.as_bytes(), vec![])
+            .map(|(_, ev)| *pipeline::downcast::<Sample>(ev.unwrap()))
+            .collect();
+        assert_eq!(
+            vec![
+                Sample {
+                    levels: Bits::from(0),
+                    unknown: Bits::from(0b00001),
+                    high_z: Bits::from(0)
+                },
+                Sample {
+                    levels: Bits::from(0),
+                    unknown: Bits::from(0b00001),
+                    high_z: Bits::from(0b11110)
+                },
+                Sample {
+                    levels: Bits::from(0b00001),
+                    unknown: Bits::from(0),
+                    high_z: Bits::from(0b11110)
+                },
+                Sample {
+                    levels: Bits::from(0b00101),
+                    unknown: Bits::from(0b00010),
+                    high_z: Bits::from(0)
+                },
+            ],
+            samples
+        );
+    }
+
+    #[test]
+    fn maps_vectors_wider_than_64_bits() {
+        let trace = format!(
+            "$var wire 1 ! clk $end
+$var wire 70 \" bus [69:0] $end
+$enddefinitions $end
+#0
+1!
+b1{} \"
+",
+            "0".repeat(69)
+        );
+        let levels: Vec<_> = VcdParser::new(trace.as_bytes(), vec![])
+            .map(|(_, ev)| pipeline::downcast::<Sample>(ev.unwrap()).levels)
+            .collect();
+        assert_eq!(
+            vec![Bits::from(1), vec![0, 70].into_iter().collect()],
+            levels
+        );
+    }
 }
@@ -1,5 +1,8 @@
-use crate::source::Sample;
-use clap::{value_t, App, Arg, ArgMatches, SubCommand};
+use crate::pipeline::{self, Event, EventIterator, Timestamp};
+use crate::source::{channels, Sample};
+use clap::{value_t, Arg, ArgMatches};
+use schemars::JsonSchema;
+use serde::Serialize;
 use std::fmt;
 use std::str::FromStr;
 
@@ -26,7 +29,8 @@ pub enum Phase {
     SecondEdge,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(tag = "kind", content = "value")]
 pub enum SpiEvent {
     ChipSelect(bool),
     Data { mosi: u8, miso: u8 },
@@ -34,10 +38,10 @@ pub enum SpiEvent {
 
 #[derive(Debug)]
 pub struct SpiBuilder {
-    cs: u8,
-    mosi: u8,
-    miso: u8,
-    clk: u8,
+    cs: usize,
+    mosi: usize,
+    miso: usize,
+    clk: usize,
     phase: Phase,
     polarity: Polarity,
     cs_active_level: Polarity,
@@ -54,19 +58,19 @@ impl SpiBuilder {
             cs_active_level: Polarity::Low,
         }
     }
-    pub fn cs(mut self, cs: u8) -> Self {
+    pub fn cs(mut self, cs: usize) -> Self {
         self.cs = cs;
         self
     }
-    pub fn clk(mut self, clk: u8) -> Self {
+    pub fn clk(mut self, clk: usize) -> Self {
         self.clk = clk;
         self
     }
-    pub fn miso(mut self, miso: u8) -> Self {
+    pub fn miso(mut self, miso: usize) -> Self {
         self.miso = miso;
         self
     }
-    pub fn mosi(mut self, mosi: u8) -> Self {
+    pub fn mosi(mut self, mosi: usize) -> Self {
         self.mosi = mosi;
         self
     }
@@ -104,12 +108,12 @@ impl SpiBuilder {
 
 pub struct Spi<T> {
     it: T,
-    pending_event: Option<(f64, SpiEvent)>,
+    pending_event: Option<(Timestamp, anyhow::Result<SpiEvent>)>,
 
-    ccs: u8,
-    cmiso: u8,
-    cmosi: u8,
-    cclk: u8,
+    ccs: usize,
+    cmiso: usize,
+    cmosi: usize,
+    cclk: usize,
 
     cs_active_level: bool,
     clk_phase: bool,
@@ -132,25 +136,27 @@ impl<T> fmt::Debug for Spi<T> {
 }
 impl<T> Iterator for Spi<T>
 where
-    T: Iterator<Item = (f64, anyhow::Result<Sample>)>,
+    T: Iterator<Item = Event>,
 {
-    type Item = (f64, anyhow::Result<SpiEvent>);
+    type Item = Event;
 
     fn next(&mut self) -> Option<Self::Item> {
         let mut ret = self.pending_event.take();
 
         while ret.is_none() {
             let (ts, sample) = match self.it.next()? {
-                (ts, Ok(Sample(sample))) => (ts, sample),
+                (ts, Ok(ev)) => (ts, *pipeline::downcast::<Sample>(ev)),
                 (ts, Err(e)) => return Some((ts, Err(e))),
             };
-            let clk = ((sample >> self.cclk) & 1) == 1;
-            let cs = ((sample >> self.ccs) & 1) == 1;
+            // an undefined chip select is considered inactive and an undefined clock holds its
+            // previous level.
+            let cs = sample.level(self.ccs).unwrap_or(!self.cs_active_level);
+            let clk = sample.level(self.cclk).unwrap_or(self.clk);
 
             if cs != self.cs {
                 self.cs = cs;
 
-                ret = Some((ts, SpiEvent::ChipSelect(cs)));
+                ret = Some((ts, Ok(SpiEvent::ChipSelect(cs))));
                 if cs {
                     self.shift_cnt = 0;
                 }
@@ -158,49 +164,56 @@ where
             if clk != self.clk {
                 self.clk = clk;
                 if cs == self.cs_active_level && clk != (self.clk_phase ^ self.clk_polarity) {
-                    self.shift_reg_mosi =
-                        self.shift_reg_mosi.wrapping_shl(1) | (((sample >> self.cmosi) & 1) as u8);
-                    self.shift_reg_miso =
-                        self.shift_reg_miso.wrapping_shl(1) | (((sample >> self.cmiso) & 1) as u8);
-                    self.shift_cnt += 1;
+                    let data = sample.level(self.cmosi).zip(sample.level(self.cmiso));
+                    let event = if let Some((mosi, miso)) = data {
+                        self.shift_reg_mosi = self.shift_reg_mosi.wrapping_shl(1) | mosi as u8;
+                        self.shift_reg_miso = self.shift_reg_miso.wrapping_shl(1) | miso as u8;
+                        self.shift_cnt += 1;
 
-                    if self.shift_cnt == 8 {
-                        self.shift_cnt = 0;
+                        if self.shift_cnt == 8 {
+                            self.shift_cnt = 0;
 
-                        let event = Some((
-                            ts,
-                            SpiEvent::Data {
-                                mosi: self.shift_reg_mosi,
-                                miso: self.shift_reg_miso,
-                            },
-                        ));
-                        if ret.is_some() {
-                            self.pending_event = event;
+                            Some((
+                                ts,
+                                Ok(SpiEvent::Data {
+                                    mosi: self.shift_reg_mosi,
+                                    miso: self.shift_reg_miso,
+                                }),
+                            ))
                         } else {
-                            ret = event;
+                            None
                         }
+                    } else {
+                        // drop the current byte
+                        self.shift_cnt = 0;
+                        Some((ts, Err(anyhow::anyhow!("Undefined level on mosi or miso"))))
+                    };
+                    if ret.is_some() {
+                        self.pending_event = event;
+                    } else {
+                        ret = event;
                     }
                 }
             }
         }
-        ret.map(|(ts, event)| (ts, Ok(event)))
+        ret.map(|(ts, event)| (ts, event.map(|event| Box::new(event) as _)))
     }
 }
 
 impl<T> Spi<T> {
-    pub fn new<'a>(input: T, matches: &ArgMatches<'a>) -> Spi<T> {
+    pub fn new(input: T, matches: &ArgMatches<'_>) -> Spi<T> {
         let (phase, polarity) = match value_t!(matches, "mode", u8).unwrap_or_else(|e| e.exit()) {
             1 => (Phase::SecondEdge, Polarity::High),
             2 => (Phase::FirstEdge, Polarity::Low),
             3 => (Phase::SecondEdge, Polarity::Low),
-            0 | _ => (Phase::FirstEdge, Polarity::High),
+            _ => (Phase::FirstEdge, Polarity::High),
         };
 
         SpiBuilder::new()
-            .cs(value_t!(matches, "cs", u8).unwrap_or_else(|e| e.exit()))
-            .miso(value_t!(matches, "miso", u8).unwrap_or_else(|e| e.exit()))
-            .mosi(value_t!(matches, "mosi", u8).unwrap_or_else(|e| e.exit()))
-            .clk(value_t!(matches, "clk", u8).unwrap_or_else(|e| e.exit()))
+            .cs(channels::value_of(matches, "cs").unwrap())
+            .miso(channels::value_of(matches, "miso").unwrap())
+            .mosi(channels::value_of(matches, "mosi").unwrap())
+            .clk(channels::value_of(matches, "clk").unwrap())
             .mode(phase, polarity)
             .cs_active_level(
                 value_t!(matches, "cs_active_level", Polarity).unwrap_or_else(|e| e.exit()),
@@ -209,15 +222,21 @@ impl<T> Spi<T> {
     }
 }
 
-pub trait SpiIteratorExt: Sized {
-    fn into_spi(self, matches: &ArgMatches) -> Spi<Self> {
-        Spi::new(self, matches)
+impl<T: 'static + Iterator<Item = Event>> EventIterator for Spi<T> {
+    fn into_iterator(self: Box<Self>) -> Box<dyn Iterator<Item = Event>> {
+        self
+    }
+    fn event_type(&self) -> std::any::TypeId {
+        std::any::TypeId::of::<SpiEvent>()
+    }
+    fn event_type_name(&self) -> &'static str {
+        std::any::type_name::<SpiEvent>()
     }
 }
-impl<T> SpiIteratorExt for T where T: Iterator<Item = (f64, anyhow::Result<Sample>)> {}
 
-pub fn args() -> [Arg<'static, 'static>; 6] {
+pub fn args() -> [Arg<'static, 'static>; 7] {
     [
+        Arg::from_usage("-v, --verbose verbose 'set to print events to stdout.'"),
         Arg::from_usage("--cs [cs] 'Channel used for the chip select.'").default_value("0"),
         Arg::from_usage("--miso [miso] 'Channel used for miso'").default_value("1"),
         Arg::from_usage("--mosi [mosi] 'Channel used for mosi'").default_value("2"),
@@ -231,6 +250,14 @@ pub fn args() -> [Arg<'static, 'static>; 6] {
     ]
 }
 
-pub fn subcommand() -> App<'static, 'static> {
-    SubCommand::with_name("spi").args(&args())
+pub fn build(pipeline: &mut Vec<Box<dyn EventIterator>>, args: &[String]) {
+    use clap::SubCommand;
+    let arg_matches = SubCommand::with_name("spi")
+        .setting(clap::AppSettings::NoBinaryName)
+        .args(&self::args())
+        .get_matches_from(args);
+
+    let it = pipeline::pop_input(pipeline, "spi", &[]).into_iterator();
+    let node = Box::new(Spi::new(it, &arg_matches));
+    pipeline.push(node);
 }
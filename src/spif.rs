@@ -1,5 +1,8 @@
+use crate::json;
+use crate::pipeline::{self, Event, EventIterator, Timestamp};
 use crate::spi::{self, SpiEvent};
-use clap::{App, ArgMatches, SubCommand};
+use schemars::JsonSchema;
+use serde::Serialize;
 use std::fmt;
 
 struct DebugVec<'a>(&'a Vec<u8>);
@@ -12,8 +15,11 @@ impl<'a> fmt::Debug for DebugVec<'a> {
     }
 }
 
+#[derive(Serialize, JsonSchema)]
 pub struct Read {
     addr: u32,
+    #[serde(serialize_with = "json::hex")]
+    #[schemars(with = "String")]
     data: Vec<u8>,
 }
 impl Read {
@@ -36,8 +42,11 @@ impl fmt::Debug for Read {
     }
 }
 
+#[derive(Serialize, JsonSchema)]
 pub struct PageProgram {
     addr: u32,
+    #[serde(serialize_with = "json::hex")]
+    #[schemars(with = "String")]
     data: Vec<u8>,
 }
 impl PageProgram {
@@ -60,19 +69,22 @@ impl fmt::Debug for PageProgram {
     }
 }
 
-pub struct SFDP {
+#[derive(Serialize, JsonSchema)]
+pub struct Sfdp {
     addr: u32,
+    #[serde(serialize_with = "json::hex")]
+    #[schemars(with = "String")]
     data: Vec<u8>,
 }
-impl SFDP {
+impl Sfdp {
     fn new() -> Self {
-        SFDP {
+        Sfdp {
             addr: 0,
             data: Vec::new(),
         }
     }
 }
-impl fmt::Debug for SFDP {
+impl fmt::Debug for Sfdp {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
@@ -84,25 +96,27 @@ impl fmt::Debug for SFDP {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Serialize, JsonSchema)]
 pub struct DeviceId {
     manufacturer: u8,
     device_id: u16,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct StatusRegister(u8);
 
+#[derive(Serialize, JsonSchema)]
+#[serde(tag = "kind", content = "value")]
 pub enum Command {
     Read(Read),
     WriteEnable,
     ResetEnable,
     Reset,
     PageProgram(PageProgram),
-    BlockErase(u32),
-    BlockErase32(u32),
-    SectorErase(u32),
-    ReadSFDP(SFDP),
+    BlockErase { addr: u32 },
+    BlockErase32 { addr: u32 },
+    SectorErase { addr: u32 },
+    ReadSFDP(Sfdp),
     ReadStatusRegister(StatusRegister),
     ReadDeviceId(DeviceId),
 }
@@ -114,9 +128,9 @@ impl fmt::Debug for Command {
             Command::ResetEnable => write!(f, "ResetEnable"),
             Command::Reset => write!(f, "Reset"),
             Command::PageProgram(pp) => pp.fmt(f),
-            Command::BlockErase(addr) => write!(f, "BlockErase({:x})", addr),
-            Command::BlockErase32(addr) => write!(f, "BlockErase32({:x})", addr),
-            Command::SectorErase(addr) => write!(f, "SectorErase({:x})", addr),
+            Command::BlockErase { addr } => write!(f, "BlockErase({:x})", addr),
+            Command::BlockErase32 { addr } => write!(f, "BlockErase32({:x})", addr),
+            Command::SectorErase { addr } => write!(f, "SectorErase({:x})", addr),
             Command::ReadSFDP(sfdp) => sfdp.fmt(f),
             Command::ReadStatusRegister(sr) => sr.fmt(f),
             Command::ReadDeviceId(did) => did.fmt(f),
@@ -125,14 +139,14 @@ impl fmt::Debug for Command {
 }
 
 enum PartialCommand {
-    Read(f64, Read),
-    ReadStatusRegister(f64),
-    PageProgram(f64, PageProgram),
-    BlockErase(f64, u32),
-    BlockErase32(f64, u32),
-    SectorErase(f64, u32),
-    ReadSFDP(f64, SFDP),
-    ReadDeviceId(f64, DeviceId),
+    Read(Timestamp, Read),
+    ReadStatusRegister(Timestamp),
+    PageProgram(Timestamp, PageProgram),
+    BlockErase(Timestamp, u32),
+    BlockErase32(Timestamp, u32),
+    SectorErase(Timestamp, u32),
+    ReadSFDP(Timestamp, Sfdp),
+    ReadDeviceId(Timestamp, DeviceId),
     None,
 }
 pub struct Spif<T> {
@@ -144,7 +158,7 @@ pub struct Spif<T> {
 }
 
 impl<T> Spif<T> {
-    fn new_cmd(&mut self, ts: f64, mosi: u8, miso: u8) -> anyhow::Result<Option<Command>> {
+    fn new_cmd(&mut self, ts: Timestamp, mosi: u8, miso: u8) -> anyhow::Result<Option<Command>> {
         self.idx = 0;
         match mosi {
             0x02 => {
@@ -169,7 +183,7 @@ impl<T> Spif<T> {
                 Ok(None)
             }
             0x5A => {
-                self.partial = PartialCommand::ReadSFDP(ts, SFDP::new());
+                self.partial = PartialCommand::ReadSFDP(ts, Sfdp::new());
                 Ok(None)
             }
             0x66 => Ok(Some(Command::ResetEnable)),
@@ -198,7 +212,11 @@ impl<T> Spif<T> {
         }
     }
 
-    fn update(&mut self, ts: f64, ev: SpiEvent) -> Option<(f64, anyhow::Result<Command>)> {
+    fn update(
+        &mut self,
+        ts: Timestamp,
+        ev: SpiEvent,
+    ) -> Option<(Timestamp, anyhow::Result<Command>)> {
         match ev {
             SpiEvent::ChipSelect(false) => {
                 self.cs = false;
@@ -245,7 +263,12 @@ impl<T> Spif<T> {
                     } else {
                         let addr = *addr;
                         self.partial = PartialCommand::None;
-                        Some((sts, Ok(Command::BlockErase((addr << 8) | (mosi as u32)))))
+                        Some((
+                            sts,
+                            Ok(Command::BlockErase {
+                                addr: (addr << 8) | (mosi as u32),
+                            }),
+                        ))
                     }
                 }
                 PartialCommand::BlockErase32(sts, ref mut addr) => {
@@ -256,7 +279,12 @@ impl<T> Spif<T> {
                     } else {
                         let addr = *addr;
                         self.partial = PartialCommand::None;
-                        Some((sts, Ok(Command::BlockErase32((addr << 8) | (mosi as u32)))))
+                        Some((
+                            sts,
+                            Ok(Command::BlockErase32 {
+                                addr: (addr << 8) | (mosi as u32),
+                            }),
+                        ))
                     }
                 }
 
@@ -268,7 +296,12 @@ impl<T> Spif<T> {
                     } else {
                         let addr = *addr;
                         self.partial = PartialCommand::None;
-                        Some((sts, Ok(Command::SectorErase((addr << 8) | (mosi as u32)))))
+                        Some((
+                            sts,
+                            Ok(Command::SectorErase {
+                                addr: (addr << 8) | (mosi as u32),
+                            }),
+                        ))
                     }
                 }
                 PartialCommand::PageProgram(_, ref mut pp) => {
@@ -316,25 +349,26 @@ impl<T> Spif<T> {
 
 impl<T> Iterator for Spif<T>
 where
-    T: Iterator<Item = (f64, anyhow::Result<SpiEvent>)>,
+    T: Iterator<Item = Event>,
 {
-    type Item = (f64, anyhow::Result<Command>);
+    type Item = Event;
 
     fn next(&mut self) -> Option<Self::Item> {
-        Some(loop {
+        let (ts, res) = loop {
             let (ts, ev) = match self.it.next()? {
-                (ts, Ok(event)) => (ts, event),
+                (ts, Ok(event)) => (ts, *pipeline::downcast::<SpiEvent>(event)),
                 (ts, Err(e)) => return Some((ts, Err(e))),
             };
             if let Some(res) = self.update(ts, ev) {
                 break res;
             }
-        })
+        };
+        Some((ts, res.map(|cmd| Box::new(cmd) as _)))
     }
 }
 
 impl<T> Spif<T> {
-    pub fn new<'a>(input: T, _matches: &ArgMatches<'a>) -> Spif<T> {
+    pub fn new(input: T) -> Spif<T> {
         Self {
             it: input,
             cs: false,
@@ -343,13 +377,28 @@ impl<T> Spif<T> {
         }
     }
 }
-pub trait SpifIteratorExt: Sized {
-    fn into_spif(self, matches: &ArgMatches) -> Spif<Self> {
-        Spif::new(self, matches)
+
+impl<T: 'static + Iterator<Item = Event>> EventIterator for Spif<T> {
+    fn into_iterator(self: Box<Self>) -> Box<dyn Iterator<Item = Event>> {
+        self
+    }
+    fn event_type(&self) -> std::any::TypeId {
+        std::any::TypeId::of::<Command>()
+    }
+    fn event_type_name(&self) -> &'static str {
+        std::any::type_name::<Command>()
     }
 }
-impl<T> SpifIteratorExt for T where T: Iterator<Item = (f64, anyhow::Result<SpiEvent>)> {}
 
-pub fn subcommand() -> App<'static, 'static> {
-    SubCommand::with_name("spif").args(&spi::args())
+pub fn build(pipeline: &mut Vec<Box<dyn EventIterator>>, args: &[String]) {
+    use clap::SubCommand;
+    let _arg_matches = SubCommand::with_name("spif")
+        .setting(clap::AppSettings::NoBinaryName)
+        .args(&spi::args())
+        .get_matches_from(args);
+
+    // the spi options are forwarded to the inserted layer
+    let it = pipeline::pop_input(pipeline, "spif", args).into_iterator();
+    let node = Box::new(Spif::new(it));
+    pipeline.push(node);
 }
@@ -0,0 +1,162 @@
+//! Stages of the pipelines, along with the events they consume and produce.
+
+use itertools::Itertools;
+
+use crate::pipeline::{EventIterator, EventType, Input, Output, Stage};
+use crate::sink::Tap;
+use crate::source::{self, Sample};
+use crate::{glitch, merge, remap, serial, spi, spif, tee, usb, window, wizfi310};
+
+const SAMPLES: EventType = EventType::of::<Sample>();
+
+const fn source(
+    name: &'static str,
+    build: fn(&mut Vec<Box<dyn EventIterator>>, &[String]),
+) -> Stage {
+    Stage {
+        name,
+        input: Input::None,
+        output: Output::Of(SAMPLES),
+        inserts: None,
+        build,
+    }
+}
+
+pub const STAGES: [Stage; 20] = [
+    source("auto", source::auto::build),
+    source("vcd", source::vcd::build),
+    source("logic", source::logic::build),
+    source("logic2", source::logic2::build),
+    source("sigrok", source::sigrok::build),
+    source("csv", source::csv::build),
+    Stage {
+        name: "window",
+        input: Input::Any,
+        output: Output::Input,
+        inserts: None,
+        build: window::build,
+    },
+    Stage {
+        name: "glitch",
+        input: Input::Of(SAMPLES),
+        output: Output::Of(SAMPLES),
+        inserts: None,
+        build: glitch::build,
+    },
+    Stage {
+        name: "remap",
+        input: Input::Of(SAMPLES),
+        output: Output::Of(SAMPLES),
+        inserts: None,
+        build: remap::build,
+    },
+    Stage {
+        name: "merge",
+        input: Input::Any,
+        output: Output::Input,
+        inserts: None,
+        build: merge::build,
+    },
+    Stage {
+        name: "tee",
+        input: Input::Of(SAMPLES),
+        output: Output::Of(EventType::of::<tee::Tagged>()),
+        inserts: None,
+        build: tee::build,
+    },
+    Stage {
+        name: "spi",
+        input: Input::Of(SAMPLES),
+        output: Output::Of(EventType::of::<spi::SpiEvent>()),
+        inserts: None,
+        build: spi::build,
+    },
+    Stage {
+        name: "spif",
+        input: Input::Of(EventType::of::<spi::SpiEvent>()),
+        output: Output::Of(EventType::of::<spif::Command>()),
+        inserts: Some("spi"),
+        build: spif::build,
+    },
+    Stage {
+        name: "serial",
+        input: Input::Of(SAMPLES),
+        output: Output::Of(EventType::of::<serial::SerialEvent>()),
+        inserts: None,
+        build: serial::build,
+    },
+    Stage {
+        name: "wizfi310",
+        input: Input::Of(EventType::of::<serial::SerialEvent>()),
+        output: Output::Of(EventType::of::<wizfi310::WizFi310Event>()),
+        inserts: Some("serial"),
+        build: wizfi310::build,
+    },
+    Stage {
+        name: "usb::signal",
+        input: Input::Of(SAMPLES),
+        output: Output::Of(EventType::of::<usb::signal::Signal>()),
+        inserts: None,
+        build: usb::signal::build,
+    },
+    Stage {
+        name: "usb::byte",
+        input: Input::Of(EventType::of::<usb::signal::Signal>()),
+        output: Output::Of(EventType::of::<usb::byte::Byte>()),
+        inserts: Some("usb::signal"),
+        build: usb::byte::build,
+    },
+    Stage {
+        name: "usb::packet",
+        input: Input::Of(EventType::of::<usb::byte::Byte>()),
+        output: Output::Of(EventType::of::<usb::packet::Packet>()),
+        inserts: Some("usb::byte"),
+        build: usb::packet::build,
+    },
+    Stage {
+        name: "usb::protocol",
+        input: Input::Of(EventType::of::<usb::packet::Packet>()),
+        output: Output::Of(EventType::of::<usb::protocol::Event>()),
+        inserts: Some("usb::packet"),
+        build: usb::protocol::build,
+    },
+    Stage {
+        name: "usb::device",
+        input: Input::Of(EventType::of::<usb::protocol::Event>()),
+        output: Output::Of(EventType::of::<usb::device::DeviceEvent>()),
+        inserts: Some("usb::protocol"),
+        build: usb::device::build,
+    },
+];
+
+/// Splits a command line at the names of the stages.
+pub fn split(argv: Vec<String>) -> Vec<(String, Vec<String>)> {
+    argv.into_iter()
+        .peekable()
+        .batching(|it| {
+            it.next().map(|subcmd| {
+                let args = it
+                    .peeking_take_while(|s| !STAGES.iter().any(|stage| stage.name == s))
+                    .collect::<Vec<_>>();
+                (subcmd, args)
+            })
+        })
+        .collect()
+}
+
+/// Builds the stages in order, the events of those given `-v` going to the sink as well.
+pub fn build(pipeline: &mut Vec<Box<dyn EventIterator>>, stages: Vec<(String, Vec<String>)>) {
+    for (sub_command, mut args) in stages {
+        let verbose = args.iter().any(|arg| arg == "-v" || arg == "--verbose");
+        args.retain(|arg| arg != "-v" && arg != "--verbose");
+        let stage = Stage::get(&sub_command);
+        if stage.is_source() {
+            source::channels::new_source();
+        }
+        (stage.build)(pipeline, &args);
+        if verbose {
+            let node = pipeline.pop().unwrap();
+            pipeline.push(Box::new(Tap::new(stage.name, node)));
+        }
+    }
+}
@@ -0,0 +1,376 @@
+//! Runs several decoder chains on the samples of a single capture.
+//!
+//! Each chain reads its own copy of the samples, the copies being kept until the slowest chain
+//! consumed them. The events of the chains are merged back in a single stream, ordered by
+//! timestamp and tagged with the name of their chain.
+//!
+//! Merging by timestamp makes a chain read ahead of the others until it emits an event, e.g. a USB
+//! chain on an idle bus while a UART chain is busy. The samples it read are kept for the other
+//! chains meanwhile, up to `--buffer` samples (about 100 bytes each for up to 64 channels), past
+//! which the pipeline fails rather than load the whole capture in memory.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::rc::Rc;
+
+use anyhow::anyhow;
+use clap::{value_t, Arg};
+use itertools::Itertools;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{Schema, SchemaObject};
+use schemars::JsonSchema;
+use serde::{Serialize, Serializer};
+use serde_json::json;
+
+use crate::json;
+use crate::pipeline::{self, Event, EventData, EventIterator};
+use crate::sink;
+use crate::source::Sample;
+use crate::stages;
+
+/// An event of a chain.
+pub struct Tagged {
+    pub chain: Rc<str>,
+    pub event: Box<dyn EventData>,
+}
+
+impl fmt::Debug for Tagged {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {:?}", self.chain, self.event)
+    }
+}
+
+/// The fields of the event, along with its chain and type.
+impl Serialize for Tagged {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut fields = json::object(self.event.to_json());
+        fields.insert("chain".to_owned(), json!(&*self.chain));
+        fields.insert(
+            "type".to_owned(),
+            json!(json::type_name(self.event.type_name())),
+        );
+        fields.serialize(serializer)
+    }
+}
+
+/// The chain, the fields of the events being those of the types declared by the chains.
+impl JsonSchema for Tagged {
+    fn schema_name() -> String {
+        "Tagged".to_owned()
+    }
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let mut schema = SchemaObject::default();
+        let properties = &mut schema.object().properties;
+        properties.insert("chain".to_owned(), gen.subschema_for::<String>());
+        schema.into()
+    }
+}
+
+struct Shared {
+    it: Box<dyn Iterator<Item = Event>>,
+    /// Samples read from the source but not yet by every branch, the first one being the
+    /// `start`th of the source.
+    samples: VecDeque<Event>,
+    start: usize,
+    /// Index in the source of the next sample of each branch, `usize::MAX` once dropped.
+    positions: Vec<usize>,
+    /// Most samples kept.
+    limit: usize,
+}
+
+/// Copy of the samples read by a chain.
+struct Branch {
+    shared: Rc<RefCell<Shared>>,
+    index: usize,
+}
+
+impl Iterator for Branch {
+    type Item = Event;
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut shared = self.shared.borrow_mut();
+        let shared = &mut *shared;
+        let position = shared.positions[self.index];
+        if position == shared.start + shared.samples.len() {
+            if shared.samples.len() >= shared.limit {
+                pipeline::fail(&format!(
+                    "tee: a chain read {} samples ahead of another one, raise --buffer to keep more of them",
+                    shared.limit
+                ));
+            }
+            let sample = shared.it.next()?;
+            shared.samples.push_back(sample);
+        }
+        shared.positions[self.index] += 1;
+
+        // the last branch to read a sample takes it, the others get a copy
+        if shared.positions.iter().all(|&other| other > position) {
+            shared.start += 1;
+            return shared.samples.pop_front();
+        }
+        let (ts, ev) = &shared.samples[position - shared.start];
+        let copy = match ev {
+            Ok(ev) => Ok(Box::new(pipeline::downcast_ref::<Sample>(ev).clone()) as _),
+            Err(e) => Err(anyhow!("{:#}", e)),
+        };
+        Some((*ts, copy))
+    }
+}
+
+impl Drop for Branch {
+    fn drop(&mut self) {
+        let mut shared = self.shared.borrow_mut();
+        shared.positions[self.index] = usize::MAX;
+        let first = shared.positions.iter().copied().min().unwrap();
+        while shared.start < first && !shared.samples.is_empty() {
+            shared.samples.pop_front();
+            shared.start += 1;
+        }
+    }
+}
+
+impl EventIterator for Branch {
+    fn into_iterator(self: Box<Self>) -> Box<dyn Iterator<Item = Event>> {
+        self
+    }
+    fn event_type(&self) -> std::any::TypeId {
+        std::any::TypeId::of::<Sample>()
+    }
+    fn event_type_name(&self) -> &'static str {
+        std::any::type_name::<Sample>()
+    }
+}
+
+/// Copies the samples for `count` chains, keeping at most `limit` of them.
+pub fn branches(
+    input: Box<dyn Iterator<Item = Event>>,
+    count: usize,
+    limit: usize,
+) -> Vec<Box<dyn EventIterator>> {
+    let shared = Rc::new(RefCell::new(Shared {
+        it: input,
+        samples: VecDeque::new(),
+        start: 0,
+        positions: vec![0; count],
+        limit,
+    }));
+    (0..count)
+        .map(|index| {
+            Box::new(Branch {
+                shared: shared.clone(),
+                index,
+            }) as _
+        })
+        .collect()
+}
+
+pub struct Tee {
+    it: Box<dyn Iterator<Item = Event>>,
+}
+
+impl Tee {
+    /// Merges the events of the chains, given along with their name.
+    pub fn new(chains: Vec<(Rc<str>, Box<dyn Iterator<Item = Event>>)>) -> Self {
+        let it = chains
+            .into_iter()
+            .enumerate()
+            .map(|(index, (chain, it))| {
+                it.map(move |(ts, ev)| {
+                    let ev = match ev {
+                        Ok(event) => Ok(Box::new(Tagged {
+                            chain: chain.clone(),
+                            event,
+                        }) as _),
+                        Err(e) => Err(anyhow!("{}: {:#}", chain, e)),
+                    };
+                    (index, (ts, ev))
+                })
+            })
+            .kmerge_by(|(a, (ta, _)), (b, (tb, _))| (ta, a) < (tb, b))
+            .map(|(_, event)| event);
+        Self { it: Box::new(it) }
+    }
+}
+
+impl Iterator for Tee {
+    type Item = Event;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.it.next()
+    }
+}
+
+impl EventIterator for Tee {
+    fn into_iterator(self: Box<Self>) -> Box<dyn Iterator<Item = Event>> {
+        self
+    }
+    fn event_type(&self) -> std::any::TypeId {
+        std::any::TypeId::of::<Tagged>()
+    }
+    fn event_type_name(&self) -> &'static str {
+        std::any::type_name::<Tagged>()
+    }
+}
+
+/// Builds the stages of a chain on top of its copy of the samples.
+fn build_chain(
+    chain: &str,
+    argv: Vec<String>,
+    input: Box<dyn EventIterator>,
+) -> Box<dyn EventIterator> {
+    let mut pipeline = vec![input];
+    stages::build(&mut pipeline, stages::split(argv));
+    if pipeline.len() != 1 {
+        pipeline::fail(&format!(
+            "The chain {} should resolve to a single iterator but {} are left",
+            chain,
+            pipeline.len()
+        ));
+    }
+    pipeline.pop().unwrap()
+}
+
+pub fn build(pipeline: &mut Vec<Box<dyn EventIterator>>, args: &[String]) {
+    let arg_matches = clap::SubCommand::with_name("tee")
+        .setting(clap::AppSettings::NoBinaryName)
+        .args(&[
+            Arg::from_usage("-v, --verbose verbose 'set to print events to stdout.'"),
+            Arg::from_usage(
+                "-c, --chain <chain>... 'Decoder chain reading the samples, as name=stages (e.g. \"uart=serial --tx 0\").'",
+            )
+            .number_of_values(1)
+            .allow_hyphen_values(true),
+            Arg::from_usage(
+                "-b, --buffer [samples] 'Most samples kept for the chains lagging behind the others.'",
+            )
+            .default_value("1000000"),
+        ])
+        .get_matches_from(args);
+    let limit = value_t!(arg_matches, "buffer", usize).unwrap_or_else(|e| e.exit());
+
+    let chains = arg_matches
+        .values_of("chain")
+        .unwrap()
+        .map(|chain| {
+            // a name is required: a chain given by its stages alone could be one of the stage
+            // names the command line is split at
+            let (name, stages) = match chain.split_once('=') {
+                Some((name, stages)) if !name.is_empty() && !name.contains(char::is_whitespace) => {
+                    (name, stages)
+                }
+                _ => pipeline::fail(&format!(
+                    "tee: expected a chain as name=stages but got {:?}",
+                    chain
+                )),
+            };
+            let argv = stages
+                .split_whitespace()
+                .map(String::from)
+                .collect::<Vec<_>>();
+            if argv.is_empty() {
+                pipeline::fail(&format!("tee: empty chain {:?}", chain));
+            }
+            (Rc::from(name), argv)
+        })
+        .collect::<Vec<_>>();
+
+    let input = pipeline::pop_input(pipeline, "tee", &[]).into_iterator();
+    let branches = branches(input, chains.len(), limit);
+    let chains = chains
+        .into_iter()
+        .zip(branches)
+        .map(|((name, argv), branch)| {
+            let node = build_chain(&name, argv, branch);
+            // the events of the chains are written as those of the tee
+            sink::declare(node.event_type());
+            (name, node.into_iterator())
+        })
+        .collect();
+    pipeline.push(Box::new(Tee::new(chains)));
+}
+
+#[cfg(test)]
+mod test {
+    use crate::pipeline::{self, Timestamp};
+    use crate::source::Sample;
+    use crate::stages;
+    use crate::test_util::{levels, samples};
+
+    fn tagged(events: impl Iterator<Item = pipeline::Event>) -> Vec<(f64, String, u64)> {
+        events
+            .map(|(ts, ev)| {
+                let tagged = pipeline::downcast::<super::Tagged>(ev.unwrap());
+                let sample = pipeline::downcast::<Sample>(tagged.event);
+                let levels = sample.levels.as_u64().unwrap();
+                (ts.as_secs_f64(), tagged.chain.to_string(), levels)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn merges_the_tagged_events_of_the_chains() {
+        let mut branches = super::branches(samples(&[(0., 1), (1., 2)]), 2, 2);
+        let delayed = branches
+            .pop()
+            .unwrap()
+            .map(|(ts, ev)| (ts + Timestamp::from_secs_f64(0.5), ev));
+        let chains = vec![
+            ("a".into(), branches.pop().unwrap().into_iterator()),
+            ("b".into(), Box::new(delayed) as Box<dyn Iterator<Item = _>>),
+        ];
+        assert_eq!(
+            vec![
+                (0., "a".to_string(), 1),
+                (0.5, "b".to_string(), 1),
+                (1., "a".to_string(), 2),
+                (1.5, "b".to_string(), 2)
+            ],
+            tagged(super::Tee::new(chains))
+        );
+    }
+
+    #[test]
+    fn builds_chains_from_the_command_line() {
+        let argv = ["tee", "-c", "early=window --end 1.5", "-c", "all=window"];
+        let stages = stages::split(argv.iter().map(|arg| arg.to_string()).collect());
+        assert_eq!(1, stages.len());
+
+        let mut pipeline = super::branches(samples(&[(0., 1), (2., 2)]), 1, 1);
+        stages::build(&mut pipeline, stages);
+        assert_eq!(
+            vec![
+                (0., "early".to_string(), 1),
+                (0., "all".to_string(), 1),
+                (2., "all".to_string(), 2)
+            ],
+            tagged(pipeline.pop().unwrap().into_iterator())
+        );
+    }
+
+    #[test]
+    fn drops_the_samples_read_by_every_chain() {
+        let input = samples(&[(0., 1), (1., 2), (2., 3), (3., 4)]);
+        let mut branches = super::branches(input, 3, 1);
+        // a finished chain no longer holds the samples
+        drop(branches.pop());
+        let mut b = branches.pop().unwrap().into_iterator();
+        let mut a = branches.pop().unwrap().into_iterator();
+        let mut read = Vec::new();
+        for _ in 0..4 {
+            read.extend(a.next());
+            read.extend(b.next());
+        }
+        assert_eq!(
+            vec![
+                (0., 1),
+                (0., 1),
+                (1., 2),
+                (1., 2),
+                (2., 3),
+                (2., 3),
+                (3., 4),
+                (3., 4)
+            ],
+            levels(read.into_iter())
+        );
+    }
+}
@@ -0,0 +1,63 @@
+//! Helpers shared by the tests.
+
+use std::io::{Read, Write};
+
+use crate::pipeline::{self, Event, Timestamp};
+use crate::source::Sample;
+
+/// Hands its data out a few bytes at a time, as pipes do, and can't be seeked.
+pub struct Trickle {
+    data: Vec<u8>,
+    pos: usize,
+    step: usize,
+}
+
+impl Trickle {
+    pub fn new(data: Vec<u8>, step: usize) -> Self {
+        Self { data, pos: 0, step }
+    }
+}
+
+impl Read for Trickle {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(self.step).min(self.data.len() - self.pos);
+        buf[..len].copy_from_slice(&self.data[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+/// Samples of the given levels, at times given in seconds.
+pub fn samples(input: &[(f64, u64)]) -> Box<dyn Iterator<Item = Event>> {
+    let events = input
+        .iter()
+        .map(|&(ts, levels)| {
+            let ts = Timestamp::from_secs_f64(ts);
+            (ts, Ok(Box::new(Sample::from(levels)) as _))
+        })
+        .collect::<Vec<Event>>();
+    Box::new(events.into_iter())
+}
+
+/// Times and levels of the samples of a stream.
+pub fn levels(events: impl Iterator<Item = Event>) -> Vec<(f64, u64)> {
+    events
+        .map(|(ts, ev)| {
+            let sample = pipeline::downcast::<Sample>(ev.unwrap());
+            (ts.as_secs_f64(), sample.levels.as_u64().unwrap())
+        })
+        .collect()
+}
+
+/// A zip archive storing `entries`, as session files do.
+pub fn zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+    use zip::write::{FileOptions, ZipWriter};
+
+    let mut zip = ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    for (name, data) in entries {
+        let options = FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        zip.start_file(*name, options).unwrap();
+        zip.write_all(data).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
@@ -2,7 +2,7 @@
 // https://www.usb.org/document-library/usb-20-specification
 // especially usb_20.pdf
 
-mod types;
+pub mod types;
 
 pub mod byte;
 pub mod device;
@@ -1,13 +1,15 @@
+use schemars::JsonSchema;
+use serde::Serialize;
 use std::collections::VecDeque;
 
 use anyhow::{anyhow, Result};
-use colored::Colorize;
 use itertools::{peek_nth, PeekNth};
 
-use super::signal::{self, Signal};
-use crate::pipeline::{self, Event, EventData, EventIterator};
+use super::signal::Signal;
+use crate::pipeline::{self, Event, EventData, EventIterator, Timestamp};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, JsonSchema)]
+#[serde(tag = "kind", content = "value")]
 pub enum Byte {
     Reset,
     Idle,
@@ -26,15 +28,14 @@ pub enum State {
 pub struct ByteIterator<T: Iterator> {
     it: PeekNth<T>,
 
-    bit_len: f64,
+    bit_len: Timestamp,
 
     state: State,
     counter: u16,
     shift_reg: u16,
     consecutive_ones: u8,
 
-    ev_queue: VecDeque<(f64, Result<Box<dyn EventData>>)>,
-    verbose: bool,
+    ev_queue: VecDeque<(Timestamp, Result<Box<dyn EventData>>)>,
 }
 
 impl<T: Iterator> ByteIterator<T> {
@@ -85,7 +86,7 @@ where
                         let t1 = *t1;
                         let sig1 = *pipeline::downcast_ref::<Signal>(sig1);
                         match self.it.peek_nth(1) {
-                            Some((t2, _)) => (t1, t2 - t1, sig1),
+                            Some((t2, _)) => (t1, *t2 - t1, sig1),
                             _ => break None,
                         }
                     }
@@ -93,22 +94,22 @@ where
                 };
 
                 if !(sig0 == sig1
-                    || ((sig1 == Signal::SE0 || sig1 == Signal::SE1) && duration < (bit_len / 2.)))
+                    || ((sig1 == Signal::SE0 || sig1 == Signal::SE1) && duration < (bit_len / 2)))
                 {
                     break Some(t1);
                 }
                 self.it.next();
             }
-            .unwrap_or(f64::INFINITY);
+            .unwrap_or(Timestamp::MAX);
 
-            let ulen = ((next_ts - t0) / self.bit_len).round() as u64;
+            let ulen = ((next_ts - t0).as_ps() as f64 / self.bit_len.as_ps() as f64).round() as u64;
             let len = next_ts - t0;
             let nts = next_ts;
 
             if sig0 == Signal::SE1 {
                 self.ev_queue
                     .push_back((t0, Err(anyhow!("Unexpected bus state"))));
-            } else if sig0 == Signal::SE0 && len > 0.010 {
+            } else if sig0 == Signal::SE0 && len > Timestamp::from_ps(10_000_000_000) {
                 self.ev_queue.push_back((t0, Ok(Box::new(Byte::Reset))));
                 self.state = State::Reset;
                 self.counter = 0;
@@ -153,7 +154,7 @@ where
                         // we only expect J with J.len >= 1bit
                         if sig0 == Signal::J && ulen >= 1 {
                             self.ev_queue
-                                .push_back((t0 - 2. * self.bit_len, Ok(Box::new(Byte::Eop))));
+                                .push_back((t0 - self.bit_len * 2, Ok(Box::new(Byte::Eop))));
                             self.state = State::Idle;
                             if ulen > 1 {
                                 self.ev_queue
@@ -191,12 +192,7 @@ where
                 self.counter -= 8;
             }
         }
-        self.ev_queue.pop_front().map(|ev| {
-            if self.verbose {
-                println!("{:10.9}: {}: {:?}", ev.0, "Byte".green().bold(), ev.1);
-            }
-            ev
-        })
+        self.ev_queue.pop_front()
     }
 }
 
@@ -204,18 +200,18 @@ impl<T: Iterator> ByteIterator<T> {
     pub fn new<'a>(input: T, matches: &clap::ArgMatches<'a>) -> Self {
         Self {
             it: peek_nth(input),
-            bit_len: 1.
-                / if matches.is_present("fs") {
+            bit_len: Timestamp::from_secs_f64(
+                1. / if matches.is_present("fs") {
                     12_000_000.
                 } else {
                     1_500_000.
                 },
+            ),
             state: State::Idle,
             counter: 0,
             shift_reg: 0,
             consecutive_ones: 0,
             ev_queue: VecDeque::new(),
-            verbose: matches.is_present("-v"),
         }
     }
 }
@@ -242,20 +238,7 @@ pub fn build(pipeline: &mut Vec<Box<dyn EventIterator>>, args: &[String]) {
         ])
         .get_matches_from(args);
 
-    if pipeline
-        .last()
-        .map(|node| node.event_type() != std::any::TypeId::of::<Signal>())
-        .unwrap_or(false)
-    {
-        signal::build(pipeline, &[]);
-    }
-
-    match pipeline.pop() {
-        None => panic!("Missing source for usb::device's parser"),
-        Some(node) => {
-            let it = node.into_iterator();
-            let node = Box::new(ByteIterator::new(it, &arg_matches));
-            pipeline.push(node);
-        }
-    }
+    let it = pipeline::pop_input(pipeline, "usb::byte", &[]).into_iterator();
+    let node = Box::new(ByteIterator::new(it, &arg_matches));
+    pipeline.push(node);
 }
@@ -1,6 +1,8 @@
 use super::protocol;
-use crate::pipeline::{self, Event as PipeEvent, EventData, EventIterator};
+use crate::pipeline::{self, Event as PipeEvent, EventData, EventIterator, Timestamp};
 use anyhow::{anyhow, Result};
+use schemars::JsonSchema;
+use serde::Serialize;
 use std::collections::HashMap;
 
 mod cdc;
@@ -11,6 +13,8 @@ mod types;
 
 mod control;
 
+#[derive(Serialize, JsonSchema)]
+#[serde(tag = "kind", content = "value")]
 #[allow(dead_code)]
 #[derive(Debug, Clone, PartialEq)]
 pub enum ClassEvent {
@@ -23,7 +27,8 @@ impl Into<InterfaceEvent> for ClassEvent {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+#[serde(tag = "kind", content = "value")]
 pub enum InterfaceEvent {
     Class(ClassEvent),
 }
@@ -33,7 +38,8 @@ impl Into<DeviceEvent> for InterfaceEvent {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+#[serde(tag = "kind", content = "value")]
 pub enum DeviceEvent {
     Reset,
     Control(control::Event),
@@ -43,7 +49,7 @@ pub enum DeviceEvent {
 trait Endpoint {
     fn update(
         &mut self,
-        timestamp: f64,
+        timestamp: Timestamp,
         transaction: protocol::Transaction,
     ) -> Option<anyhow::Result<DeviceEvent>>;
 }
@@ -128,20 +134,7 @@ pub fn build(pipeline: &mut Vec<Box<dyn EventIterator>>, args: &[String]) {
         ))
         .get_matches_from(args);
 
-    if pipeline
-        .last()
-        .map(|node| node.event_type() != std::any::TypeId::of::<protocol::Event>())
-        .unwrap_or(false)
-    {
-        protocol::build(pipeline, &[]);
-    }
-
-    match pipeline.pop() {
-        None => panic!("Missing source for usb::device's parser"),
-        Some(node) => {
-            let it = node.into_iterator();
-            let node = Box::new(DeviceEventIterator::new(it));
-            pipeline.push(node);
-        }
-    }
+    let it = pipeline::pop_input(pipeline, "usb::device", &[]).into_iterator();
+    let node = Box::new(DeviceEventIterator::new(it));
+    pipeline.push(node);
 }
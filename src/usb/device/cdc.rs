@@ -1,13 +1,26 @@
 use itertools::Itertools;
+use schemars::JsonSchema;
+use serde::Serialize;
 use std::convert::TryFrom;
 
+use crate::json;
+use crate::pipeline::Timestamp;
 use crate::usb::protocol::Transaction;
 use crate::usb::types::HandShake;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(tag = "kind", content = "value")]
 pub enum Event {
-    Rx(Vec<u8>),
-    Tx(Vec<u8>),
+    Rx(
+        #[serde(serialize_with = "json::hex")]
+        #[schemars(with = "String")]
+        Vec<u8>,
+    ),
+    Tx(
+        #[serde(serialize_with = "json::hex")]
+        #[schemars(with = "String")]
+        Vec<u8>,
+    ),
 }
 
 impl Into<super::ClassEvent> for Event {
@@ -16,7 +29,8 @@ impl Into<super::ClassEvent> for Event {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(tag = "kind", content = "value")]
 pub enum DeviceSubClass {
     Reserved0,
     DirectLineControlModel,
@@ -62,24 +76,25 @@ impl TryFrom<(u8, u8)> for DeviceSubClass {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
 pub struct InterfaceSubClass {
     pub subclass: u8,
     pub protocol: u8,
 }
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
 pub struct DataInterfaceSubClass {
     pub subclass: u8,
     pub protocol: u8,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
 pub struct InterfaceDescriptor;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
 pub struct EndpointDescriptor;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(tag = "kind", content = "value")]
 pub enum ClassSpecificDescriptor {
     Interface(InterfaceDescriptor),
     Endpoint(EndpointDescriptor),
@@ -93,7 +108,6 @@ impl ClassSpecificDescriptor {
             .next()
             .ok_or_else(|| anyhow::anyhow!("Truncated descriptor"))?;
 
-        println!("CDC descriptor: {:x?}", &response[..desc_length.into()]);
         Ok((
             &response[desc_length.into()..],
             match desc_type {
@@ -113,7 +127,7 @@ pub struct CdCEndpoint(pub u8);
 impl super::Endpoint for CdCEndpoint {
     fn update(
         &mut self,
-        _timestamp: f64,
+        _timestamp: Timestamp,
         transaction: Transaction,
     ) -> Option<anyhow::Result<super::DeviceEvent>> {
         let Transaction {
@@ -1,24 +1,37 @@
 #![allow(dead_code)]
 
 use super::types::*;
+use crate::json;
+use crate::pipeline::Timestamp;
 use crate::usb::protocol::Transaction;
 use crate::usb::types::*;
+use schemars::JsonSchema;
+use serde::Serialize;
 
 use anyhow::anyhow;
 use std::collections::HashMap;
 use std::convert::TryFrom;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum Event {}
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+#[serde(tag = "kind", content = "value")]
+pub enum Event {
+    /// A completed request along with the data of its data phase.
+    Request(Request, Option<Response>),
+}
 impl Into<super::DeviceEvent> for Event {
     fn into(self) -> super::DeviceEvent {
         super::DeviceEvent::Control(self)
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(tag = "kind", content = "value")]
 pub enum Response {
-    Unknown(Vec<u8>),
+    Unknown(
+        #[serde(serialize_with = "json::hex")]
+        #[schemars(with = "String")]
+        Vec<u8>,
+    ),
     Descriptor(Descriptor),
 }
 
@@ -47,7 +60,7 @@ impl ControlEndpoint {
 impl ControlEndpoint {
     pub(super) fn update(
         &mut self,
-        _timestamp: f64,
+        _timestamp: Timestamp,
         transaction: Transaction,
         endpoints: &mut HashMap<usize, Box<dyn super::Endpoint>>,
     ) -> Option<anyhow::Result<super::DeviceEvent>> {
@@ -151,9 +164,6 @@ impl ControlEndpoint {
                         let is_zlp = data.payload.is_empty();
                         buf.extend(data.payload);
 
-                        if is_zlp {
-                            println!("got a zlp",);
-                        }
                         if is_zlp || buf.len() == buf.capacity() {
                             self.request_state =
                                 RequestState::Status(request.clone(), buffer.take(), false);
@@ -216,30 +226,23 @@ impl ControlEndpoint {
                         bail!(self, err)
                     }
 
-                    let _request = *request;
-                    let _buffer = buffer.take();
+                    let request = *request;
+                    let buffer = buffer.take();
                     self.request_state = RequestState::Idle;
 
-                    let response = _buffer.map(|buffer| -> Box<dyn std::fmt::Debug> {
-                        match _request.request {
+                    let response = match (request.request, buffer) {
+                        (
                             ERequest::Device(DeviceRequest::Standard(
                                 StandardRequest::GetDescriptor(descriptor_type),
-                            )) => match Descriptor::try_from((descriptor_type, buffer)) {
-                                Ok(desc) => Box::new(desc),
-                                Err(e) => Box::new(e),
-                            },
-                            _ => Box::new(buffer),
-                        }
-                    });
-
-                    println!("{:.9}: {:x?}: {:x?}", _timestamp, _request, response);
-                    break;
-                    //if let Request { request_type: RequestType::Standard, request: RequestGet, value, index, length }
-
-                    //return Some(Ok(Operation::Control(
-                    //Request::Request(request),
-                    //buffer.map(Response::Unknown),
-                    //)));
+                            )),
+                            Some(buffer),
+                        ) => match Descriptor::try_from((descriptor_type, buffer)) {
+                            Ok(desc) => Some(Response::Descriptor(desc)),
+                            Err(e) => return Some(Err(e.context(format!("{:x?}", request)))),
+                        },
+                        (_, buffer) => buffer.map(Response::Unknown),
+                    };
+                    return Some(Ok(Event::Request(request, response).into()));
                 }
 
                 (_, _) => {
@@ -1,6 +1,9 @@
+use schemars::JsonSchema;
+use serde::Serialize;
 use std::convert::From;
 
-#[derive(Copy, Clone, PartialEq, Eq)]
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(tag = "kind", content = "value")]
 pub enum LanguageId {
     CodeArray,
     Language(u16),
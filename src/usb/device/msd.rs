@@ -1,13 +1,17 @@
-#[derive(Debug, Clone, PartialEq, Eq)]
+use crate::pipeline::Timestamp;
+use schemars::JsonSchema;
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
 pub enum Event {}
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
 pub struct InterfaceSubClass {
     pub subclass: u8,
     pub protocol: u8,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
 pub struct ClassSpecificDescriptor;
 impl ClassSpecificDescriptor {
     pub fn parse(response: &[u8]) -> anyhow::Result<(&[u8], Self)> {
@@ -19,7 +23,7 @@ pub struct MsdEndpoint;
 impl super::Endpoint for MsdEndpoint {
     fn update(
         &mut self,
-        _timestamp: f64,
+        _timestamp: Timestamp,
         _transaction: super::protocol::Transaction,
     ) -> Option<anyhow::Result<super::DeviceEvent>> {
         None
@@ -7,7 +7,13 @@ use super::lang_id::*;
 
 //use crate::usb::types::*;
 
+use crate::json;
+use anyhow::Context;
 use itertools::Itertools;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde::Serialize;
 use std::convert::From;
 use std::convert::TryFrom;
 use std::convert::TryInto;
@@ -35,7 +41,7 @@ pub enum Recipient {
     Reserved(u8),
 }
 
-#[derive(PartialEq, Debug, Copy, Clone)]
+#[derive(PartialEq, Debug, Copy, Clone, Serialize, JsonSchema)]
 pub enum RequestType {
     Standard,
     Class,
@@ -43,7 +49,7 @@ pub enum RequestType {
     Reserved,
 }
 
-#[derive(PartialEq, Debug, Copy, Clone)]
+#[derive(PartialEq, Debug, Copy, Clone, Serialize, JsonSchema)]
 pub enum DataPhaseTransferDirection {
     In,
     Out,
@@ -83,7 +89,8 @@ impl PackedRequestType {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, JsonSchema)]
+#[serde(tag = "kind", content = "value")]
 pub enum DeviceRequest {
     Standard(StandardRequest),
     Class(),
@@ -111,7 +118,8 @@ impl TryFrom<(RequestType, u8, u16, u16)> for DeviceRequest {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, JsonSchema)]
+#[serde(tag = "kind", content = "value")]
 pub enum ERequest {
     Device(DeviceRequest),
     Interface {
@@ -162,7 +170,7 @@ impl TryFrom<(Recipient, RequestType, u8, u16, u16)> for ERequest {
     }
 }
 
-#[derive(PartialEq, Clone, Copy)]
+#[derive(PartialEq, Clone, Copy, Serialize, JsonSchema)]
 pub struct Request {
     pub direction: DataPhaseTransferDirection,
     pub request: ERequest,
@@ -202,7 +210,8 @@ impl TryFrom<&[u8]> for Request {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(tag = "kind", content = "value")]
 pub enum StandardRequest {
     GetStatus,
     ClearFeature,
@@ -244,7 +253,8 @@ impl TryFrom<(u8, u16, u16)> for StandardRequest {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(tag = "kind", content = "value")]
 pub enum GetDescriptorType {
     Device,
     Configuration(u8),
@@ -275,7 +285,8 @@ impl TryFrom<(u16, u16)> for GetDescriptorType {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(into = "u8")]
 pub enum MaxPacketSize {
     _8,
     _16,
@@ -294,8 +305,27 @@ impl From<u8> for MaxPacketSize {
         }
     }
 }
+impl From<MaxPacketSize> for u8 {
+    fn from(size: MaxPacketSize) -> Self {
+        match size {
+            MaxPacketSize::_8 => 8,
+            MaxPacketSize::_16 => 16,
+            MaxPacketSize::_32 => 32,
+            MaxPacketSize::_64 => 64,
+            MaxPacketSize::Reserved(size) => size,
+        }
+    }
+}
+impl JsonSchema for MaxPacketSize {
+    fn schema_name() -> String {
+        "MaxPacketSize".to_owned()
+    }
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        u8::json_schema(gen)
+    }
+}
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, JsonSchema)]
 pub struct UsbVersion(pub u16);
 impl std::fmt::Debug for UsbVersion {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -309,12 +339,13 @@ impl std::fmt::Debug for UsbVersion {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
 pub enum MiscellaneousSubClass {
     InterfaceAssociationDescriptor,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(tag = "kind", content = "value")]
 pub enum DeviceClass {
     CommunicationDevice(cdc::DeviceSubClass),
     Miscellaneous(MiscellaneousSubClass),
@@ -336,11 +367,24 @@ impl TryFrom<(u8, u8, u8)> for DeviceClass {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(tag = "kind", content = "value")]
 pub enum DeviceCapabilityDescriptor {
-    USB20Extensions { link_power_management: bool },
-    Unimplemented(u8, Vec<u8>),
-    Reserved(u8, Vec<u8>),
+    USB20Extensions {
+        link_power_management: bool,
+    },
+    Unimplemented(
+        u8,
+        #[serde(serialize_with = "json::hex")]
+        #[schemars(with = "String")]
+        Vec<u8>,
+    ),
+    Reserved(
+        u8,
+        #[serde(serialize_with = "json::hex")]
+        #[schemars(with = "String")]
+        Vec<u8>,
+    ),
 }
 impl DeviceCapabilityDescriptor {
     fn parse(buffer: &[u8]) -> anyhow::Result<(&[u8], Self)> {
@@ -395,7 +439,7 @@ impl DeviceCapabilityDescriptor {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
 pub struct BinaryObjectStore(pub Vec<DeviceCapabilityDescriptor>);
 impl BinaryObjectStore {
     fn parse(buffer: &[u8]) -> anyhow::Result<Self> {
@@ -437,16 +481,21 @@ impl BinaryObjectStore {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, JsonSchema)]
 pub struct DeviceRelease(pub u16);
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(tag = "kind", content = "value")]
 pub enum Descriptor {
     Device(DeviceDescriptor),
     String(StringDescriptor),
     Configuration(ConfigurationDescriptor),
     BinaryObjectStore(BinaryObjectStore),
-    Reserved(Vec<u8>),
+    Reserved(
+        #[serde(serialize_with = "json::hex")]
+        #[schemars(with = "String")]
+        Vec<u8>,
+    ),
 }
 
 impl TryFrom<(GetDescriptorType, Vec<u8>)> for Descriptor {
@@ -492,7 +541,7 @@ impl TryFrom<(GetDescriptorType, Vec<u8>)> for Descriptor {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
 pub struct DeviceDescriptor {
     usb_version: UsbVersion,
     device_class: DeviceClass,
@@ -558,7 +607,8 @@ impl TryFrom<[u8; 18]> for DeviceDescriptor {
         })
     }
 }
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(tag = "kind", content = "value")]
 pub enum StringDescriptor {
     CodeArray(Vec<LanguageId>),
     String(String),
@@ -597,15 +647,30 @@ impl StringDescriptor {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(into = "u16")]
 pub struct MaxPower(pub u8);
+/// The power in mA.
+impl From<MaxPower> for u16 {
+    fn from(power: MaxPower) -> Self {
+        u16::from(power.0) * 2
+    }
+}
+impl JsonSchema for MaxPower {
+    fn schema_name() -> String {
+        "MaxPower".to_owned()
+    }
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        u16::json_schema(gen)
+    }
+}
 impl std::fmt::Debug for MaxPower {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "\"{}mA\"", u16::from(self.0) * 2)
+        write!(f, "\"{}mA\"", u16::from(*self))
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
 pub struct ConfigurationCharacteristics {
     self_powered: bool,
     remote_wakeup: bool,
@@ -626,7 +691,7 @@ impl TryFrom<u8> for ConfigurationCharacteristics {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
 pub struct ConfigurationDescriptor {
     configuration_value: u8,
     description_string_index: u8,
@@ -668,10 +733,8 @@ impl ConfigurationDescriptor {
         let mut read_ptr = &response[9..];
 
         while !read_ptr.is_empty() {
-            let (new_read_ptr, interface) = InterfaceDescriptor::parse(read_ptr).map_err(|e| {
-                println!("{:?} --- {:?}", interfaces.len(), e);
-                e
-            })?;
+            let (new_read_ptr, interface) = InterfaceDescriptor::parse(read_ptr)
+                .with_context(|| format!("In interface {}", interfaces.len()))?;
             read_ptr = new_read_ptr;
             interfaces.push(interface);
         }
@@ -686,14 +749,20 @@ impl ConfigurationDescriptor {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(tag = "kind", content = "value")]
 pub enum ClassSpecificDescriptor {
     CommunicationDevice(cdc::ClassSpecificDescriptor),
     MassStorageDevice(msd::ClassSpecificDescriptor),
-    Other(Vec<u8>),
+    Other(
+        #[serde(serialize_with = "json::hex")]
+        #[schemars(with = "String")]
+        Vec<u8>,
+    ),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(tag = "kind", content = "value")]
 pub enum InterfaceClass {
     CommunicationDevice(cdc::InterfaceSubClass),
     CDCData(cdc::DataInterfaceSubClass),
@@ -736,7 +805,8 @@ impl TryFrom<(u8, u8, u8)> for InterfaceClass {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(tag = "kind", content = "value")]
 pub enum InterfaceDescriptor {
     Plain(PlainInterfaceDescriptor),
     Association(InterfaceAssociationDescriptor),
@@ -764,7 +834,7 @@ impl InterfaceDescriptor {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
 pub struct PlainInterfaceDescriptor {
     id: u8,
     alternate_setting: u8,
@@ -838,7 +908,7 @@ impl PlainInterfaceDescriptor {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
 pub struct InterfaceAssociationDescriptor {
     first_interface: u8,
     interfaces: Vec<PlainInterfaceDescriptor>,
@@ -892,13 +962,13 @@ impl InterfaceAssociationDescriptor {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
 pub enum EndpointDirection {
     In,
     Out,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
 pub enum SyncType {
     NoSynchronization,
     Asynchronous,
@@ -906,7 +976,7 @@ pub enum SyncType {
     Synchronous,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
 pub enum UsageType {
     DataEndpoint,
     FeedbackEndpoint,
@@ -914,7 +984,8 @@ pub enum UsageType {
     Reserved,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(tag = "kind", content = "value")]
 pub enum TransferType {
     Control,
     Isochronous {
@@ -965,7 +1036,7 @@ impl TryFrom<u8> for TransferType {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
 pub struct EndpointDescriptor {
     endpoint_number: u8,
     direction: EndpointDirection,
@@ -1,12 +1,15 @@
+use schemars::JsonSchema;
+use serde::Serialize;
 use std::convert::TryFrom;
 
 use anyhow::Result;
 
-use super::byte::{self, Byte};
+use super::byte::Byte;
 use super::types::{crc16, crc5, Data, DataPID, HandShake, Token, TokenType};
 use crate::pipeline::{self, Event, EventData, EventIterator};
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+#[serde(tag = "kind", content = "value")]
 pub enum Packet {
     Reset,
     SoF(u16),
@@ -144,20 +147,7 @@ pub fn build(pipeline: &mut Vec<Box<dyn EventIterator>>, args: &[String]) {
         ))
         .get_matches_from(args);
 
-    if pipeline
-        .last()
-        .map(|node| node.event_type() != std::any::TypeId::of::<Byte>())
-        .unwrap_or(false)
-    {
-        byte::build(pipeline, &[]);
-    }
-
-    match pipeline.pop() {
-        None => panic!("Missing source for usb::protocol's parser"),
-        Some(node) => {
-            let it = node.into_iterator();
-            let node = Box::new(PacketIterator::new(it));
-            pipeline.push(node);
-        }
-    }
+    let it = pipeline::pop_input(pipeline, "usb::packet", &[]).into_iterator();
+    let node = Box::new(PacketIterator::new(it));
+    pipeline.push(node);
 }
@@ -1,9 +1,12 @@
-use super::packet::{self, Packet};
+use super::packet::Packet;
 use super::types::{Data, HandShake, Token};
 use crate::pipeline::{self, Event as PipeEvent, EventData, EventIterator};
 use anyhow::Result;
+use schemars::JsonSchema;
+use serde::Serialize;
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(tag = "kind", content = "value")]
 pub enum Event {
     Reset,
     Sof(u16),
@@ -17,7 +20,7 @@ pub enum TransactionState {
     Data { token: Token, data: Option<Data> },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct Transaction {
     pub token: Token,
     pub data: Option<Data>,
@@ -122,20 +125,7 @@ pub fn build(pipeline: &mut Vec<Box<dyn EventIterator>>, args: &[String]) {
         ))
         .get_matches_from(args);
 
-    if pipeline
-        .last()
-        .map(|node| node.event_type() != std::any::TypeId::of::<Packet>())
-        .unwrap_or(false)
-    {
-        packet::build(pipeline, &[]);
-    }
-
-    match pipeline.pop() {
-        None => panic!("Missing source for usb::protocol's parser"),
-        Some(node) => {
-            let it = node.into_iterator();
-            let node = Box::new(ProtocolIterator::new(it));
-            pipeline.push(node);
-        }
-    }
+    let it = pipeline::pop_input(pipeline, "usb::protocol", &[]).into_iterator();
+    let node = Box::new(ProtocolIterator::new(it));
+    pipeline.push(node);
 }
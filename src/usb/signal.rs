@@ -1,9 +1,11 @@
-use clap::{value_t, ArgMatches};
+use clap::ArgMatches;
+use schemars::JsonSchema;
+use serde::Serialize;
 
 use crate::pipeline::{self, Event, EventIterator};
-use crate::source::Sample;
+use crate::source::{channels, Sample};
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, JsonSchema)]
 pub enum Signal {
     SE0,
     J,
@@ -14,11 +16,11 @@ pub enum Signal {
 pub struct SignalIterator<T> {
     it: T,
     fs: bool,
-    dp_mask: u64,
-    dm_mask: u64,
+    dp: usize,
+    dm: usize,
 
     current_signal: Option<Signal>,
-    verbose: bool,
+    undefined: bool,
 }
 
 impl<T> Iterator for SignalIterator<T>
@@ -33,10 +35,21 @@ where
                 (ts, Err(e)) => break (ts, Err(e)),
             };
 
-            let smp = pipeline::downcast::<Sample>(event).0;
+            let smp = *pipeline::downcast::<Sample>(event);
 
-            let dp = (smp & self.dp_mask) == self.dp_mask;
-            let dm = (smp & self.dm_mask) == self.dm_mask;
+            let (dp, dm) = match smp.level(self.dp).zip(smp.level(self.dm)) {
+                Some(levels) => levels,
+                None => {
+                    // report once and resync on the next defined bus state.
+                    if !self.undefined {
+                        self.undefined = true;
+                        self.current_signal = None;
+                        break (ts, Err(anyhow::anyhow!("Undefined level on D+ or D-")));
+                    }
+                    continue;
+                }
+            };
+            self.undefined = false;
 
             let s = match (dp, dm, self.fs) {
                 (true, true, _) => Signal::SE1,
@@ -50,13 +63,9 @@ where
                 .unwrap_or(true)
             {
                 self.current_signal = Some(s);
-                if self.verbose {}
                 break (ts, Ok(Box::new(s)));
             }
         };
-        if self.verbose {
-            println!("{:.9}: {:?}", res.0, res.1);
-        }
         Some(res)
     }
 }
@@ -65,10 +74,10 @@ impl<T> SignalIterator<T> {
         Self {
             it: input,
             fs: matches.is_present("fs"),
-            dp_mask: 1 << value_t!(matches, "dp", u8).unwrap_or_else(|e| e.exit()),
-            dm_mask: 1 << value_t!(matches, "dm", u8).unwrap_or_else(|e| e.exit()),
+            dp: channels::value_of(matches, "dp").unwrap(),
+            dm: channels::value_of(matches, "dm").unwrap(),
             current_signal: None,
-            verbose: matches.is_present("verbose"),
+            undefined: false,
         }
     }
 }
@@ -96,21 +105,7 @@ pub fn build(pipeline: &mut Vec<Box<dyn EventIterator>>, args: &[String]) {
         ])
         .get_matches_from(args);
 
-    if let Some(node) = pipeline.last() {
-        if node.event_type() != std::any::TypeId::of::<Sample>() {
-            panic!(
-                "Invalid input type. Exected Samples but got {}",
-                node.event_type_name()
-            )
-        }
-    }
-
-    match pipeline.pop() {
-        None => panic!("Missing source for usb::signal's parser"),
-        Some(node) => {
-            let it = node.into_iterator();
-            let node = Box::new(SignalIterator::new(it, &arg_matches));
-            pipeline.push(node);
-        }
-    }
+    let it = pipeline::pop_input(pipeline, "usb::signal", &[]).into_iterator();
+    let node = Box::new(SignalIterator::new(it, &arg_matches));
+    pipeline.push(node);
 }
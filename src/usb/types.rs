@@ -1,7 +1,10 @@
 #![allow(dead_code)]
+use crate::json;
+use schemars::JsonSchema;
+use serde::Serialize;
 use std::fmt::Debug;
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, JsonSchema)]
 pub enum TokenType {
     Setup,
     Out,
@@ -9,14 +12,14 @@ pub enum TokenType {
     Ping,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, JsonSchema)]
 pub struct Token {
     pub token_type: TokenType,
     pub address: u8,
     pub endpoint: u8,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, JsonSchema)]
 pub enum DataPID {
     Data0,
     Data1,
@@ -24,13 +27,15 @@ pub enum DataPID {
     MData,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
 pub struct Data {
     pub pid: DataPID,
+    #[serde(serialize_with = "json::hex")]
+    #[schemars(with = "String")]
     pub payload: Vec<u8>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, JsonSchema)]
 pub enum HandShake {
     Ack,
     NAck,
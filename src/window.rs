@@ -0,0 +1,177 @@
+//! Keeps the events of a time window.
+
+use std::any::TypeId;
+
+use clap::{Arg, ArgMatches};
+
+use crate::pipeline::{self, Event, EventIterator, Timestamp};
+use crate::source::Sample;
+
+pub struct Window<T> {
+    it: T,
+    start: Timestamp,
+    end: Timestamp,
+    event_type: (TypeId, &'static str),
+
+    /// Last sample before the window, emitted at its start so that decoders see the actual
+    /// state of the lines rather than a spurious edge.
+    state: Option<Sample>,
+    started: bool,
+    pending: Option<Event>,
+}
+
+impl<T> Window<T> {
+    pub fn new(input: T, event_type: (TypeId, &'static str), matches: &ArgMatches<'_>) -> Self {
+        let time = |arg| {
+            matches.value_of(arg).map(|v| {
+                pipeline::parse_time(v).unwrap_or_else(|e| {
+                    clap::Error::value_validation_auto(format!("{}: {}", arg, e)).exit()
+                })
+            })
+        };
+        let start = time("start").unwrap_or(Timestamp::MIN);
+        let end = match (time("end"), time("duration")) {
+            (Some(end), _) => end,
+            // from the origin when no start is given
+            (None, Some(duration)) if start == Timestamp::MIN => duration,
+            (None, Some(duration)) => start + duration,
+            (None, None) => Timestamp::MAX,
+        };
+
+        Self {
+            it: input,
+            start,
+            end,
+            event_type,
+            state: None,
+            started: false,
+            pending: None,
+        }
+    }
+}
+
+impl<T> Iterator for Window<T>
+where
+    T: Iterator<Item = Event>,
+{
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (ts, ev) = match self.pending.take().or_else(|| self.it.next()) {
+                Some(event) => event,
+                None if !self.started && self.start != Timestamp::MIN => {
+                    // the capture ended before the window: its last state lasts
+                    self.started = true;
+                    let state = self.state.take()?;
+                    return Some((self.start, Ok(Box::new(state))));
+                }
+                None => return None,
+            };
+
+            if ts < self.start {
+                if let Ok(ev) = ev {
+                    if self.event_type.0 == TypeId::of::<Sample>() {
+                        self.state = Some(*pipeline::downcast::<Sample>(ev));
+                    }
+                }
+                continue;
+            }
+
+            if !self.started {
+                self.started = true;
+                if let Some(state) = self.state.take().filter(|_| ts > self.start) {
+                    self.pending = Some((ts, ev));
+                    return Some((self.start, Ok(Box::new(state))));
+                }
+            }
+
+            // stops reading the input as soon as possible
+            if ts > self.end {
+                return None;
+            }
+            return Some((ts, ev));
+        }
+    }
+}
+
+impl<T: 'static + Iterator<Item = Event>> EventIterator for Window<T> {
+    fn into_iterator(self: Box<Self>) -> Box<dyn Iterator<Item = Event>> {
+        self
+    }
+    fn event_type(&self) -> TypeId {
+        self.event_type.0
+    }
+    fn event_type_name(&self) -> &'static str {
+        self.event_type.1
+    }
+}
+
+pub fn build(pipeline: &mut Vec<Box<dyn EventIterator>>, args: &[String]) {
+    let arg_matches = clap::SubCommand::with_name("window")
+        .setting(clap::AppSettings::NoBinaryName)
+        .args(&[
+            Arg::from_usage("-v, --verbose verbose 'set to print events to stdout.'"),
+            Arg::from_usage(
+                "--start [time] 'Start of the window, in seconds unless suffixed by ms, us or ns.'",
+            ),
+            Arg::from_usage("--end [time] 'End of the window.'"),
+            Arg::from_usage("--duration [time] 'Duration of the window.'").conflicts_with("end"),
+        ])
+        .get_matches_from(args);
+
+    let node = pipeline::pop_input(pipeline, "window", &[]);
+    let event_type = (node.event_type(), node.event_type_name());
+    let it = node.into_iterator();
+    let node = Box::new(Window::new(it, event_type, &arg_matches));
+    pipeline.push(node);
+}
+
+#[cfg(test)]
+mod test {
+    use std::any::TypeId;
+
+    use crate::source::Sample;
+    use crate::test_util::{levels, samples};
+
+    fn window(args: &[&str], input: &[(f64, u64)]) -> Vec<(f64, u64)> {
+        let matches = clap::SubCommand::with_name("window")
+            .setting(clap::AppSettings::NoBinaryName)
+            .args(&[
+                clap::Arg::from_usage("--start [time]"),
+                clap::Arg::from_usage("--end [time]"),
+                clap::Arg::from_usage("--duration [time]"),
+            ])
+            .get_matches_from(args);
+        let event_type = (TypeId::of::<Sample>(), "Sample");
+        levels(super::Window::new(samples(input), event_type, &matches))
+    }
+
+    #[test]
+    fn emits_the_state_at_the_window_start() {
+        let samples = [(0., 0), (1., 1), (2., 3), (3., 2), (4., 0)];
+        assert_eq!(
+            vec![(1.5, 1), (2., 3), (3., 2)],
+            window(&["--start", "1.5", "--end", "3.5"], &samples)
+        );
+        assert_eq!(
+            vec![(2., 3), (3., 2)],
+            window(&["--start", "2000ms", "--duration", "1s"], &samples)
+        );
+        assert_eq!(vec![(5., 0)], window(&["--start", "5"], &samples));
+    }
+
+    #[test]
+    fn counts_the_duration_from_a_negative_start() {
+        let samples = [(-3., 0), (-2., 1), (-1., 3), (0., 2)];
+        assert_eq!(
+            vec![(-2.5, 0), (-2., 1)],
+            window(&["--start=-2.5", "--duration", "1"], &samples)
+        );
+        // without a start, the window ends a duration after the origin
+        assert_eq!(
+            vec![(-3., 0), (-2., 1), (-1., 3), (0., 2)],
+            window(&["--duration", "1"], &samples)
+        );
+    }
+}
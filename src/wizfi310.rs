@@ -1,15 +1,18 @@
+use crate::pipeline::{self, Event, EventIterator};
 use crate::serial::{self, SerialEvent};
-use clap::{App, ArgMatches, SubCommand};
+use schemars::JsonSchema;
+use serde::Serialize;
 use std::net::Ipv4Addr;
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(tag = "kind", content = "value")]
 pub enum WizFi310Event {
     Command(String),
     Sent(String),
     Recv(RecvHeader, String),
     Resp(String),
 }
-#[derive(Debug)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct RecvHeader {
     socket_id: u8,
     ip: Ipv4Addr,
@@ -28,14 +31,14 @@ pub struct Wizfi310<T> {
 
 impl<T> Iterator for Wizfi310<T>
 where
-    T: Iterator<Item = (f64, anyhow::Result<SerialEvent>)>,
+    T: Iterator<Item = Event>,
 {
-    type Item = (f64, anyhow::Result<WizFi310Event>);
+    type Item = Event;
 
     fn next(&mut self) -> Option<Self::Item> {
         let out = loop {
             let (ts, ev) = match self.it.next()? {
-                (ts, Ok(ev)) => (ts, ev),
+                (ts, Ok(ev)) => (ts, *pipeline::downcast::<SerialEvent>(ev)),
                 (ts, Err(e)) => return Some((ts, Err(e))),
             };
             match ev {
@@ -71,13 +74,17 @@ where
                             );
                         }
                     } else if (c as char) == '\n' {
-                        if self.rx.starts_with("[") && self.rx.ends_with("]\r\n") {
-                            if self.rx.contains(",") {
-                                let line: String =
-                                    self.rx.chars().skip(1).take(self.rx.len() - 4).collect();
-                                self.data_to_send =
-                                    line.split(',').last().and_then(|v| v.parse().ok()).unwrap()
-                            }
+                        if self.rx.starts_with("[")
+                            && self.rx.ends_with("]\r\n")
+                            && self.rx.contains(",")
+                        {
+                            let line: String =
+                                self.rx.chars().skip(1).take(self.rx.len() - 4).collect();
+                            self.data_to_send = line
+                                .split(',')
+                                .next_back()
+                                .and_then(|v| v.parse().ok())
+                                .unwrap()
                         }
                         let mut v = String::new();
                         std::mem::swap(&mut v, &mut self.rx);
@@ -108,12 +115,12 @@ where
             //      has buf len reached expected length ?
             //
         };
-        Some(out)
+        Some((out.0, out.1.map(|ev| Box::new(ev) as _)))
     }
 }
 
 impl<T> Wizfi310<T> {
-    pub fn new<'a>(input: T, _matches: &ArgMatches<'a>) -> Wizfi310<T> {
+    pub fn new(input: T) -> Wizfi310<T> {
         Self {
             it: input,
             data_to_send: 0,
@@ -124,13 +131,28 @@ impl<T> Wizfi310<T> {
         }
     }
 }
-pub trait Wizfi310IteratorExt: Sized {
-    fn into_wizfi310(self, matches: &ArgMatches) -> Wizfi310<Self> {
-        Wizfi310::new(self, matches)
+
+impl<T: 'static + Iterator<Item = Event>> EventIterator for Wizfi310<T> {
+    fn into_iterator(self: Box<Self>) -> Box<dyn Iterator<Item = Event>> {
+        self
+    }
+    fn event_type(&self) -> std::any::TypeId {
+        std::any::TypeId::of::<WizFi310Event>()
+    }
+    fn event_type_name(&self) -> &'static str {
+        std::any::type_name::<WizFi310Event>()
     }
 }
-impl<T> Wizfi310IteratorExt for T where T: Iterator<Item = (f64, anyhow::Result<SerialEvent>)> {}
 
-pub fn subcommand() -> App<'static, 'static> {
-    SubCommand::with_name("wizfi310").args(&serial::args())
+pub fn build(pipeline: &mut Vec<Box<dyn EventIterator>>, args: &[String]) {
+    use clap::SubCommand;
+    let _arg_matches = SubCommand::with_name("wizfi310")
+        .setting(clap::AppSettings::NoBinaryName)
+        .args(&serial::args())
+        .get_matches_from(args);
+
+    // the serial options are forwarded to the inserted layer
+    let it = pipeline::pop_input(pipeline, "wizfi310", args).into_iterator();
+    let node = Box::new(Wizfi310::new(it));
+    pipeline.push(node);
 }